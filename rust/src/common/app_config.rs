@@ -1,62 +1,285 @@
+use super::{
+    audit::AuditLog,
+    config_file,
+    health::Health,
+    ip_filter::{Cidr, IpFilter},
+    known_hosts::KnownHosts,
+    listener::ListenAddress,
+    logger::{Format, Level, Logger, Output, Period, Rotation},
+    metrics::Metrics,
+};
 use argh::FromArgs;
-use chrono::prelude::*;
-use futures::{channel::mpsc, lock::Mutex, SinkExt, StreamExt};
+use hyper::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::{
     net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
+    time::Duration,
 };
-use tokio::io::AsyncWriteExt;
 
 #[derive(Debug)]
 pub struct AppConfig {
-    pub listen_address: SocketAddr,
+    pub listen_addresses: Vec<ListenAddress>,
+    pub listen_hosts: Vec<String>,
+    pub redirect_http: Option<SocketAddr>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub base_path: String,
+    pub no_tls: bool,
     pub certificate: Option<String>,
+    pub certificate_dir: Option<String>,
+    pub certificate_expiry_margin: Duration,
+    pub ip_filter: IpFilter,
+    pub sign_in_ip_filter: IpFilter,
+    pub trusted_proxies: Vec<Cidr>,
+    pub trusted_origins: Vec<url::Origin>,
+    pub query_token: bool,
+    pub security_headers: Vec<(HeaderName, HeaderValue)>,
     pub private_key: Option<String>,
+    pub client_ca: Option<String>,
+    pub client_cert_required: bool,
+    pub client_cert_users: Vec<(String, Vec<String>)>,
     pub logger: Logger,
+    pub audit_log: AuditLog,
+    pub metrics: Metrics,
+    pub health: Health,
     pub local_ssh_port: String,
+    pub known_hosts: KnownHosts,
+    pub allow_hosts: Vec<String>,
+    pub ssh_agent: bool,
+    pub login_window: Duration,
+    pub login_max_failures: usize,
+    pub login_backoff: Duration,
+    pub login_ban: Duration,
+    pub session_lifetime: Option<Duration>,
+    pub session_idle_timeout: Option<Duration>,
+    pub resume_grace: Option<Duration>,
+    pub shutdown_timeout: Duration,
+    pub state_dir: PathBuf,
     pub bin: String,
 
     // internal use
     pub assets_path: Option<PathBuf>,
-    pub client: Option<String>,
+    pub client: bool,
 }
 
 impl AppConfig {
     pub fn new() -> Self {
-        let opt: Options = argh::from_env();
-        let listen_address = opt
-            .listen_address
-            .unwrap_or_else(|| "localhost:7200".to_string())
-            .to_socket_addrs()
-            .expect("--listen-address argument format error")
-            .as_slice()[0];
+        let opt = load_options();
+        let listen = opt.listen_address;
+        let mut listen_addresses = Vec::new();
+        let mut listen_hosts = Vec::new();
+        for listen in listen.iter() {
+            if let Some(path) = listen.strip_prefix("unix:") {
+                listen_addresses.push(ListenAddress::Unix(PathBuf::from(path)));
+                continue;
+            }
+            // every resolved address, e.g. both ::1 and 127.0.0.1 of localhost
+            let resolved = listen.to_socket_addrs().unwrap_or_else(|e| {
+                panic!("--listen-address argument format error: {} ({})", listen, e)
+            });
+            for address in resolved.map(ListenAddress::Tcp) {
+                if !listen_addresses.contains(&address) {
+                    listen_addresses.push(address);
+                }
+            }
+            if let Some((host, _)) = listen.rsplit_once(':') {
+                listen_hosts.push(
+                    host.trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .to_string(),
+                );
+            }
+        }
         let bin = std::env::current_exe()
             .ok()
             .and_then(|p| p.to_str().map(|s| s.to_string()))
             .expect("current operation system doesn't support (can't get bin file path)");
 
+        let output = match opt.disable_logger {
+            true => Output::None,
+            false => match opt.logger {
+                // stdout of internal client carries the pipe to master
+                None if opt.client => Output::Stderr,
+                None => Output::Stdio,
+                Some(path) => Output::File(
+                    PathBuf::from(path),
+                    Rotation {
+                        max_size: opt.log_max_size.unwrap_or_default() * 1024 * 1024,
+                        period: match opt.log_rotate {
+                            Some(period) => period.parse().unwrap_or_else(|e| {
+                                panic!("--log-rotate argument format error: {}", e)
+                            }),
+                            None => Period::Never,
+                        },
+                        keep: opt.log_keep.unwrap_or_default(),
+                    },
+                ),
+            },
+        };
+        let level = match &opt.log_level {
+            Some(level) => level
+                .parse()
+                .unwrap_or_else(|e| panic!("--log-level argument format error: {}", e)),
+            None => Level::Info,
+        };
+        let format = match &opt.log_format {
+            Some(format) => format
+                .parse()
+                .unwrap_or_else(|e| panic!("--log-format argument format error: {}", e)),
+            None => Format::Text,
+        };
+        let logger = Logger::new(output, level, format).expect("Failed to open log file. ");
+        let audit_log = match (&opt.audit_log, opt.client) {
+            (Some(path), false) => AuditLog::open(path.as_ref())
+                .unwrap_or_else(|e| panic!("--audit-log argument format error: {}: {}", path, e)),
+            _ => AuditLog::default(),
+        };
+        // internal client doesn't connect to any ssh server
+        let known_hosts = match opt.client {
+            true => KnownHosts::default(),
+            false => match KnownHosts::load(&opt.known_hosts) {
+                Ok(known_hosts) => known_hosts,
+                Err(e) => {
+                    logger.err(format!("Failed to load known hosts: {:?}", e));
+                    KnownHosts::default()
+                }
+            },
+        };
+        for skipped in known_hosts.skipped() {
+            logger.warn(format!("Skip known hosts entry {}", skipped));
+        }
+        if !opt.client && known_hosts.is_empty() {
+            logger.err("No trusted ssh host key found and every sign in will be refused. Please check out '--known-hosts' argument. ");
+        }
+
+        for address in listen_addresses.iter() {
+            match address {
+                ListenAddress::Tcp(address) if opt.no_tls && !opt.force_no_tls && !address.ip().is_loopback() => panic!(
+                    "--no-tls argument refuses to serve plain http on non-loopback address {}, set '--force-no-tls' if it's intended",
+                    address
+                ),
+                _ => {}
+            }
+        }
+
+        // client certificate comes with tls handshake, that a proxy in front terminates
+        if opt.no_tls && opt.client_ca.is_some() {
+            panic!("--client-ca argument can't work with '--no-tls', browser has no tls handshake with this server");
+        }
+
+        let ip_filter = IpFilter::new(&opt.allow, &opt.deny)
+            .unwrap_or_else(|e| panic!("--allow/--deny argument format error: {}", e));
+        let sign_in_ip_filter = IpFilter::new(&opt.sign_in_allow, &opt.sign_in_deny)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "--sign-in-allow/--sign-in-deny argument format error: {}",
+                    e
+                )
+            });
+
         AppConfig {
-            listen_address,
+            listen_addresses,
+            listen_hosts,
+            redirect_http: opt.redirect_http.map(|address| {
+                match address.to_socket_addrs().map(|mut a| a.next()) {
+                    Ok(Some(address)) => address,
+                    _ => panic!("--redirect-http argument format error: {}", address),
+                }
+            }),
+            metrics_listen: opt.metrics_listen.map(|address| {
+                match address.to_socket_addrs().map(|mut a| a.next()) {
+                    Ok(Some(address)) => address,
+                    _ => panic!("--metrics-listen argument format error: {}", address),
+                }
+            }),
+            base_path: match opt.base_path.as_deref().map(|p| p.trim_end_matches('/')) {
+                None | Some("") => String::new(),
+                Some(path) if path.starts_with('/') && !path.contains(['?', '#']) => {
+                    path.to_string()
+                }
+                Some(path) => panic!("--base-path argument format error: {}", path),
+            },
+            no_tls: opt.no_tls,
             certificate: opt.certificate,
+            certificate_dir: opt.certificate_dir,
+            certificate_expiry_margin: Duration::from_secs(
+                opt.certificate_expiry_margin.unwrap_or_default() * 24 * 60 * 60,
+            ),
+            ip_filter,
+            sign_in_ip_filter,
+            trusted_proxies: opt
+                .trusted_proxy
+                .iter()
+                .map(|s| {
+                    s.parse()
+                        .unwrap_or_else(|e| panic!("--trusted-proxy argument format error: {}", e))
+                })
+                .collect(),
+            trusted_origins: opt
+                .trusted_origin
+                .iter()
+                .map(|origin| match url::Url::parse(origin) {
+                    Ok(url) if url.origin().is_tuple() => url.origin(),
+                    _ => panic!("--trusted-origin argument format error: {}", origin),
+                })
+                .chain(own_origins(&listen, opt.no_tls))
+                .collect(),
+            query_token: opt.query_token,
+            security_headers: security_headers(&opt.security_header),
             private_key: opt.private_key,
-            logger: match opt.disable_logger {
-                true => Logger::None,
-                false => match opt.logger {
-                    None => Logger::Stdio,
-                    Some(path) => {
-                        let (tx, rx) = mpsc::channel::<String>(64);
-                        tokio::spawn(run_file_logger(rx, path.clone()));
-                        Logger::File(Mutex::new(tx), path)
-                    }
-                },
+            client_ca: opt.client_ca,
+            client_cert_required: match opt.client_cert_mode.as_deref() {
+                None | Some("map") => false,
+                Some("strict") => true,
+                Some(mode) => panic!("--client-cert-mode argument format error: {}", mode),
             },
+            client_cert_users: opt
+                .client_cert_user
+                .iter()
+                .map(|entry| match entry.split_once('=') {
+                    Some((name, users)) => (
+                        name.to_string(),
+                        users.split(',').map(|u| u.trim().to_string()).collect(),
+                    ),
+                    None => panic!("--client-cert-user argument format error: {}", entry),
+                })
+                .collect(),
+            logger,
+            audit_log,
+            metrics: Metrics::default(),
+            health: Health::default(),
             assets_path: match opt.assets_path {
                 Some(p) => Some(PathBuf::from(p)),
                 None => None,
             },
-            local_ssh_port: match opt.local_ssh_port {
-                Some(address) => address,
-                None => "22".to_string(),
+            local_ssh_port: opt.local_ssh_port.unwrap_or_default(),
+            known_hosts,
+            allow_hosts: opt.allow_host,
+            ssh_agent: opt.ssh_agent,
+            login_window: Duration::from_secs(opt.login_window.unwrap_or_default()),
+            login_max_failures: opt.login_max_failures.unwrap_or_default().max(1),
+            login_backoff: Duration::from_secs(opt.login_backoff.unwrap_or_default()),
+            login_ban: Duration::from_secs(opt.login_ban.unwrap_or_default()),
+            session_lifetime: match opt.session_lifetime {
+                Some(0) | None => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+            },
+            session_idle_timeout: match opt.session_idle_timeout {
+                Some(0) | None => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+            },
+            resume_grace: match opt.resume_grace.unwrap_or_default() {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            shutdown_timeout: Duration::from_secs(opt.shutdown_timeout.unwrap_or_default()),
+            state_dir: match opt.state_dir {
+                Some(path) => PathBuf::from(path),
+                None => dirs::state_dir()
+                    .or_else(dirs::data_local_dir)
+                    .map(|dir| dir.join("web-ssh-tool"))
+                    .expect("current operation system doesn't support (can't get state directory), please set '--state-dir' argument"),
             },
             client: opt.client,
             bin,
@@ -64,122 +287,341 @@ impl AppConfig {
     }
 }
 
-async fn run_file_logger(mut rx: mpsc::Receiver<String>, path: String) {
-    let mut option = tokio::fs::OpenOptions::new();
-    option.create(true).write(true).append(true);
-    let mut file = option.open(&path).await.expect("Failed to open log file. ");
-    let mut has_lost_some_log = false;
-    while let Some(message) = rx.next().await {
-        if let Err(e) = file.write_all(message.as_bytes()).await {
-            if !has_lost_some_log {
-                eprintln!(
-                    "Failed to write log to file ({:?}). Trying reopen log file. ",
-                    e
-                );
-            }
-            match option.open(&path).await {
-                Ok(f) => file = f,
-                Err(_) => {}
-            }
-            if let Err(e) = file.write_all(message.as_bytes()).await {
-                if !has_lost_some_log {
-                    eprintln!("Failed to reopen log file and write log to file ({:?}). Output log to stdio. ", e);
-                }
-                println!("{}", message);
-                has_lost_some_log = true;
-            }
-        } else {
-            if has_lost_some_log {
-                if let Ok(_) = file
-                    .write_all(format!(
-                        "{} [ERROR] Some log messages have been lost. Please check out the lost messages in app stdio. \n",
-                        Utc::now().format("%+")
-                    ).as_bytes())
-                    .await
-                {
-                    has_lost_some_log = false;
-                }
-            }
+impl AppConfig {
+    /// Port of the first tcp listen address, that browser visits.
+    pub fn tcp_port(&self) -> Option<u16> {
+        self.listen_addresses
+            .iter()
+            .find_map(|address| match address {
+                ListenAddress::Tcp(address) => Some(address.port()),
+                ListenAddress::Unix(_) => None,
+            })
+    }
+
+    /// Path of request relative to '--base-path' (empty for the base path itself without trailing slash),
+    /// none if it's out of base path.
+    pub fn route_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match path.strip_prefix(self.base_path.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => Some(rest),
+            _ => None,
         }
     }
+
+    /// Client certificate (by its common name) must be presented and allow the username to sign in
+    /// if client ca is set. Any valid certificate is enough if no '--client-cert-user' is set.
+    pub fn is_client_cert_allowed(&self, common_name: &Option<String>, username: &str) -> bool {
+        use super::wildcard_match;
+        if self.client_ca.is_none() {
+            return true;
+        }
+        let common_name = match common_name {
+            Some(common_name) => common_name,
+            None => return false,
+        };
+        if self.client_cert_users.is_empty() {
+            return true;
+        }
+        self.client_cert_users.iter().any(|(name, users)| {
+            name == common_name && users.iter().any(|user| wildcard_match(user, username))
+        })
+    }
+
+    /// Whether the client certificate is mapped to exactly the username by '--client-cert-user',
+    /// so that the browser is tied to the username before ssh authentication.
+    pub fn is_client_cert_bound(&self, common_name: &Option<String>, username: &str) -> bool {
+        match (&self.client_ca, common_name) {
+            (Some(_), Some(common_name)) => self.client_cert_users.iter().any(|(name, users)| {
+                name == common_name && users.iter().any(|user| user == username)
+            }),
+            _ => false,
+        }
+    }
+
+    /// Pattern `host` allows any port of the host and `host:port` allows only that port.
+    pub fn is_host_allowed(&self, host: &str, port: &str) -> bool {
+        self.allow_hosts
+            .iter()
+            .any(|pattern| host_matches(pattern, host, port))
+    }
+}
+
+/// Match `host`, `host:port` or `[ipv6]:port` pattern, a bare ipv6 address is a host pattern.
+fn host_matches(pattern: &str, host: &str, port: &str) -> bool {
+    use super::wildcard_match;
+    match pattern.rsplit_once(':') {
+        Some((h, p)) if !h.contains(':') || h.ends_with(']') => {
+            wildcard_match(h.trim_start_matches('[').trim_end_matches(']'), host)
+                && wildcard_match(p, port)
+        }
+        _ => wildcard_match(pattern, host),
+    }
 }
 
 impl std::fmt::Display for AppConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "AppConfig: ")?;
-        writeln!(f, "   listen_address:     {}", self.listen_address)?;
+        writeln!(
+            f,
+            "   listen_addresses:   {:?}",
+            self.listen_addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<_>>()
+        )?;
+        writeln!(f, "   no_tls:             {}", self.no_tls)?;
         writeln!(f, "   certificate:        {:?}", self.certificate)?;
+        writeln!(f, "   certificate_dir:    {:?}", self.certificate_dir)?;
+        writeln!(
+            f,
+            "   certificate_expiry_margin: {:?}",
+            self.certificate_expiry_margin
+        )?;
         writeln!(f, "   private_key:        {:?}", self.private_key)?;
+        writeln!(f, "   client_ca:          {:?}", self.client_ca)?;
+        writeln!(f, "   client_cert_required: {}", self.client_cert_required)?;
+        writeln!(f, "   client_cert_users:  {:?}", self.client_cert_users)?;
+        writeln!(f, "   ip_filter:          {}", self.ip_filter)?;
+        writeln!(f, "   sign_in_ip_filter:  {}", self.sign_in_ip_filter)?;
+        writeln!(
+            f,
+            "   trusted_proxies:    {:?}",
+            self.trusted_proxies
+                .iter()
+                .map(|cidr| cidr.to_string())
+                .collect::<Vec<_>>()
+        )?;
+        writeln!(
+            f,
+            "   trusted_origins:    {:?}",
+            self.trusted_origins
+                .iter()
+                .map(|origin| origin.ascii_serialization())
+                .collect::<Vec<_>>()
+        )?;
+        writeln!(f, "   query_token:        {}", self.query_token)?;
+        writeln!(f, "   redirect_http:      {:?}", self.redirect_http)?;
+        writeln!(f, "   metrics_listen:     {:?}", self.metrics_listen)?;
+        writeln!(f, "   base_path:          {:?}", self.base_path)?;
+        writeln!(f, "   security_headers:   {:?}", self.security_headers)?;
         writeln!(f, "   logger:             {:?}", self.logger)?;
+        writeln!(f, "   audit_log:          {:?}", self.audit_log)?;
         writeln!(f, "   local_ssh_port:     {}", self.local_ssh_port)?;
+        writeln!(f, "   known_hosts:        {}", self.known_hosts)?;
+        writeln!(f, "   allow_hosts:        {:?}", self.allow_hosts)?;
+        writeln!(f, "   ssh_agent:          {}", self.ssh_agent)?;
+        writeln!(f, "   login_window:       {:?}", self.login_window)?;
+        writeln!(f, "   login_max_failures: {}", self.login_max_failures)?;
+        writeln!(f, "   login_backoff:      {:?}", self.login_backoff)?;
+        writeln!(f, "   login_ban:          {:?}", self.login_ban)?;
+        writeln!(f, "   session_lifetime:   {:?}", self.session_lifetime)?;
+        writeln!(
+            f,
+            "   session_idle_timeout: {:?}",
+            self.session_idle_timeout
+        )?;
+        writeln!(f, "   resume_grace:       {:?}", self.resume_grace)?;
+        writeln!(f, "   shutdown_timeout:   {:?}", self.shutdown_timeout)?;
+        writeln!(f, "   state_dir:          {:?}", self.state_dir)?;
         writeln!(f, "   bin:                {:?}", self.bin)?;
         Ok(())
     }
 }
 
-pub enum Logger {
-    None,
-    Stdio,
-    File(Mutex<mpsc::Sender<String>>, String),
+const DEFAULT_SECURITY_HEADERS: &[(&str, &str)] = &[
+    ("strict-transport-security", "max-age=31536000"),
+    // bundled assets only, previews are framed by the page itself
+    (
+        "content-security-policy",
+        "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data: blob:; media-src 'self' blob:; font-src 'self' data:; connect-src 'self'; worker-src 'self' blob:; frame-src 'self' blob:; frame-ancestors 'self'; object-src 'none'; base-uri 'self'; form-action 'self'",
+    ),
+    ("x-content-type-options", "nosniff"),
+    ("x-frame-options", "SAMEORIGIN"),
+    ("referrer-policy", "no-referrer"),
+];
+
+/// Origins of this server by the listen addresses, loopback names for loopback and unspecified
+/// hosts, because names that the host is visited by can't be told from the request itself.
+fn own_origins(listen: &[String], no_tls: bool) -> Vec<url::Origin> {
+    const LOOPBACK: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+    let schemes: &[&str] = match no_tls {
+        true => &["https", "http"],
+        false => &["https"],
+    };
+    let mut origins = Vec::new();
+    for listen in listen.iter().filter(|listen| !listen.starts_with("unix:")) {
+        let (host, port) = match listen.rsplit_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        let ip = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>();
+        let hosts = match ip {
+            Ok(ip) if ip.is_unspecified() || ip.is_loopback() => LOOPBACK.to_vec(),
+            _ if host.is_empty() || host == "localhost" => LOOPBACK.to_vec(),
+            _ => vec![host],
+        };
+        for scheme in schemes {
+            for host in hosts.iter() {
+                if let Ok(url) = url::Url::parse(&format!("{}://{}:{}", scheme, host, port)) {
+                    if !origins.contains(&url.origin()) {
+                        origins.push(url.origin());
+                    }
+                }
+            }
+        }
+    }
+    origins
 }
 
-// @TODO: file logger implement
+/// Default security headers overridden by '--security-header' arguments in order.
+fn security_headers(overrides: &[String]) -> Vec<(HeaderName, HeaderValue)> {
+    let mut headers: Vec<(HeaderName, HeaderValue)> = DEFAULT_SECURITY_HEADERS
+        .iter()
+        .map(|(name, value)| {
+            (
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            )
+        })
+        .collect();
+    fn error(entry: &str) -> ! {
+        panic!("--security-header argument format error: {}", entry)
+    }
+    for entry in overrides {
+        let (name, value) = entry.split_once(':').unwrap_or_else(|| error(entry));
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).unwrap_or_else(|_| error(entry));
+        headers.retain(|(n, _)| n != name);
+        let value = value.trim();
+        if !value.is_empty() {
+            let value = HeaderValue::from_str(value).unwrap_or_else(|_| error(entry));
+            headers.push((name, value));
+        }
+    }
+    headers
+}
 
-impl Logger {
-    #[allow(dead_code)]
-    pub fn info<T: std::fmt::Display>(&self, message: T) {
-        match self {
-            Logger::Stdio => {
-                println!("{} [INFO]  {}", Utc::now().format("%+"), message)
-            }
-            Logger::File(tx, _) => {
-                futures::executor::block_on(async {
-                    let _ = tx
-                        .lock()
-                        .await
-                        .send(format!("{} [INFO]  {}\n", Utc::now().format("%+"), message))
-                        .await;
-                });
-            }
-            Logger::None => {}
+/// Arguments override environment variables that override configuration file, key by key.
+/// Internal client takes arguments only.
+fn load_options() -> Options {
+    let to_table = |options: &Options| {
+        serde_json::to_value(options).expect("settings are always serializable")
+    };
+    let settings = match to_table(&Options::default()) {
+        Value::Object(table) => table,
+        _ => Map::new(),
+    };
+    // not settings of the file but still taken from environment variables
+    let mut defaults = settings.clone();
+    defaults.insert("config".to_string(), Value::Null);
+    defaults.insert("print-config".to_string(), Value::Bool(false));
+
+    // '--<switch>=false' turns off a switch that an earlier source turned on
+    let argv = std::env::args().collect::<Vec<_>>();
+    let (argv, args_off) = config_file::switch_values(&argv[1..], &defaults)
+        .unwrap_or_else(|e| panic!("Argument format error: {}", e));
+    let args = parse_args(&argv);
+    if args.client {
+        return args.with_defaults();
+    }
+
+    let env_args = config_file::env_args(&defaults)
+        .unwrap_or_else(|e| panic!("Environment variable format error: {}", e));
+    let (env_args, env_off) = config_file::switch_values(&env_args, &defaults)
+        .unwrap_or_else(|e| panic!("Environment variable format error: {}", e));
+    let env_args = env_args.iter().map(String::as_str).collect::<Vec<_>>();
+    let env = Options::from_args(&["web-ssh-tool"], &env_args).unwrap_or_else(|e| {
+        panic!(
+            "Environment variable format error: {}",
+            config_file::env_error(&e.output)
+        )
+    });
+
+    let mut merged = Map::new();
+    if let Some(path) = args.config.as_ref().or(env.config.as_ref()) {
+        let file = config_file::load(path)
+            .unwrap_or_else(|e| panic!("--config argument format error: {}: {}", path, e));
+        // tell the bad key before merging
+        if let Err(e) = serde_path_to_error::deserialize::<_, Options>(Value::Object(file.clone()))
+        {
+            panic!("--config argument format error: {}: {}", path, describe(e));
         }
+        merged.extend(file);
     }
-    #[allow(dead_code)]
-    pub fn err<T: std::fmt::Display>(&self, message: T) {
-        match self {
-            Logger::Stdio => {
-                eprintln!("{} [ERROR] {}", Utc::now().format("%+"), message)
+    merged.extend(config_file::set_only(to_table(&env)));
+    let is_setting = |(key, _): &(String, Value)| settings.contains_key(key);
+    merged.extend(env_off.into_iter().filter(is_setting));
+    merged.extend(config_file::set_only(to_table(&args)));
+    merged.extend(args_off.into_iter().filter(is_setting));
+
+    let options: Options = serde_path_to_error::deserialize(Value::Object(merged))
+        .unwrap_or_else(|e| panic!("Settings format error: {}", describe(e)));
+    let options = options.with_defaults();
+    if args.print_config || env.print_config {
+        match toml::to_string(&options) {
+            Ok(text) => print!("{}", text),
+            Err(e) => eprintln!("Failed to print settings: {}", e),
+        }
+        std::process::exit(0);
+    }
+    options
+}
+
+/// Same as `argh::from_env` but with the given arguments.
+fn parse_args(args: &[String]) -> Options {
+    let command = std::env::args().next().unwrap_or_default();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match Options::from_args(&[command.as_str()], &args) {
+        Ok(options) => options,
+        Err(exit) => match exit.status {
+            Ok(()) => {
+                println!("{}", exit.output);
+                std::process::exit(0);
             }
-            Logger::File(tx, _) => {
-                futures::executor::block_on(async {
-                    let _ = tx
-                        .lock()
-                        .await
-                        .send(format!("{} [ERROR] {}\n", Utc::now().format("%+"), message))
-                        .await;
-                });
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {} --help for more information.",
+                    exit.output, command
+                );
+                std::process::exit(1);
             }
-            Logger::None => {}
-        }
+        },
     }
 }
 
-impl std::fmt::Debug for Logger {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::None => write!(f, "None"),
-            Self::Stdio => write!(f, "Stdio"),
-            Self::File(_, path) => write!(f, "File({})", path),
-        }
+fn describe(error: serde_path_to_error::Error<serde_json::Error>) -> String {
+    match error.path().to_string().as_str() {
+        "." => error.inner().to_string(),
+        key => format!("'{}' {}", key, error.inner()),
     }
 }
 
-#[derive(FromArgs)]
+#[derive(FromArgs, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
 /// AppConfig
 struct Options {
-    /// server listen address (default: 127.0.0.1:7200, example: 0.0.0.0:8080)
+    /// read settings from TOML (or YAML if the extension is .yaml/.yml) file whose keys are the long argument names, overridden by WEB_SSH_TOOL_* environment variables and then arguments (example: /etc/web-ssh-tool.toml)
+    #[argh(option)]
+    #[serde(skip)]
+    config: Option<String>,
+
+    /// print the settings merged from configuration file, environment variables and arguments as TOML and exit
+    #[argh(switch)]
+    #[serde(skip)]
+    print_config: bool,
+
+    /// server listen address, every address the host resolves to or a unix domain socket path, can be repeated (default: localhost:7200, example: 0.0.0.0:8080, [::]:8080 or unix:/run/web-ssh-tool.sock)
     #[argh(option, short = 'l')]
-    listen_address: Option<String>,
+    listen_address: Vec<String>,
+
+    /// serve plain http (http1 and h2c) for a tls terminating reverse proxy in front, only on loopback address unless '--force-no-tls' is set
+    #[argh(switch)]
+    no_tls: bool,
+
+    /// allow '--no-tls' to listen on non-loopback address, only for private network between proxy and this server
+    #[argh(switch)]
+    force_no_tls: bool,
 
     /// use custom tls certificate path (example: pem/test.crt)
     #[argh(option, short = 'c')]
@@ -189,23 +631,227 @@ struct Options {
     #[argh(option, short = 'k')]
     private_key: Option<String>,
 
+    /// directory of more certificates picked by the host name browser visits, '<name>.crt' with '<name>.key' or '<name>.p12' with optional '<name>.pass', the '-c' one serves other names (example: /etc/web-ssh-tool/certs)
+    #[argh(option)]
+    certificate_dir: Option<String>,
+
+    /// '/healthz' and '/readyz' fail once a loaded certificate expires within the days, so that it gets renewed before browsers refuse it (default: 14, example: 30)
+    #[argh(option)]
+    certificate_expiry_margin: Option<u64>,
+
+    /// verify browser tls client certificate against the CA bundle and require it to sign in, not available with '--no-tls' (example: pem/client-ca.crt)
+    #[argh(option)]
+    client_ca: Option<String>,
+
+    /// client certificate mode, 'map': client without certificate can load the page but can't sign in, 'strict': client without certificate can't connect at all (default: map)
+    #[argh(option)]
+    client_cert_mode: Option<String>,
+
+    /// usernames that client certificate with the common name can sign in as, can be repeated (default: any username for any valid certificate, example: alice-laptop=alice,git or admin-phone=*)
+    #[argh(option)]
+    client_cert_user: Vec<String>,
+
+    /// reverse proxy address or CIDR whose 'Forwarded' (or 'X-Forwarded-For' if absent) header tells the real client address for logs and ip filters, can be repeated (default: none, example: 127.0.0.1)
+    #[argh(option)]
+    trusted_proxy: Vec<String>,
+
+    /// only accept connection from address or CIDR, can be repeated (default: any, example: 10.8.0.0/16 or fd00::/8)
+    #[argh(option)]
+    allow: Vec<String>,
+
+    /// refuse connection from address or CIDR before tls handshake, can be repeated and it wins over '--allow' (example: 10.8.0.100/32)
+    #[argh(option)]
+    deny: Vec<String>,
+
+    /// only allow sign in websocket from address or CIDR, checked after '--allow'/'--deny' and can be repeated (default: any, example: 10.8.1.0/24)
+    #[argh(option)]
+    sign_in_allow: Vec<String>,
+
+    /// refuse sign in websocket from address or CIDR, can be repeated and it wins over '--sign-in-allow' (example: 127.0.0.1)
+    #[argh(option)]
+    sign_in_deny: Vec<String>,
+
+    /// trust websocket from web page of the origin besides the listen addresses (and loopback names for loopback or unspecified ones), needed when visited by other names or through a proxy, can be repeated (example: https://ssh.example.com)
+    #[argh(option)]
+    trusted_origin: Vec<String>,
+
+    /// accept deprecated session token from query string ('?t=') that leaks into browser history and proxy logs (default: false)
+    #[argh(switch)]
+    query_token: bool,
+
+    /// serve the app under the path prefix instead of root, e.g. behind a reverse proxy hosting other apps (default: /, example: /ssh)
+    #[argh(option)]
+    base_path: Option<String>,
+
+    /// listen address that redirects every plain http request to the https origin (example: 0.0.0.0:80)
+    #[argh(option)]
+    redirect_http: Option<String>,
+
+    /// listen address of a plain http server exposing Prometheus metrics at /metrics, keep it private to the monitoring network (default: disabled, example: 127.0.0.1:9100)
+    #[argh(option)]
+    metrics_listen: Option<String>,
+
+    /// set, replace or remove (with empty value) a security header of http responses, can be repeated (default: strict transport security, content security policy for bundled assets and more, example: 'Strict-Transport-Security: max-age=63072000' or 'Content-Security-Policy:')
+    #[argh(option)]
+    security_header: Vec<String>,
+
     /// use app log output, if this argument is set, stdio log output will be disable (default: stdio, example: /tmp/my.log)
     #[argh(option)]
     logger: Option<String>,
 
+    /// lowest level of log messages to output, one of debug, info, warn and error (default: info, example: debug)
+    #[argh(option)]
+    log_level: Option<String>,
+
+    /// log line format, text or json (one object a line with time, level, message and fields like session, username, peer, route and duration_ms) (default: text, example: json)
+    #[argh(option)]
+    log_format: Option<String>,
+
+    /// rotate the log file of '--logger' when it grows over the size in MiB, 0 for no limit (default: 0, example: 100)
+    #[argh(option)]
+    log_max_size: Option<u64>,
+
+    /// rotate the log file of '--logger' every period, never, hourly or daily (default: never, example: daily)
+    #[argh(option)]
+    log_rotate: Option<String>,
+
+    /// how many rotated log files (<logger>.1, <logger>.2 ...) to keep (default: 5, example: 30)
+    #[argh(option)]
+    log_keep: Option<usize>,
+
     /// whether or not disable logger, if disable is true, '--logger' argument become useless (default: false)
     #[argh(switch)]
     disable_logger: bool,
 
+    /// append sign ins, shells, file changes, uploads and downloads as JSON lines to the file, written by this server rather than the internal client of signed in user (default: none, example: /var/log/web-ssh-tool/audit.log)
+    #[argh(option)]
+    audit_log: Option<String>,
+
     /// use custom ssh port (default: 22, example: 8080)
     #[argh(option)]
     local_ssh_port: Option<String>,
 
+    /// use known hosts file to verify ssh server host keys in addition to local host keys (default: /etc/ssh/ssh_host_*_key.pub only, example: /etc/ssh/ssh_known_hosts)
+    #[argh(option)]
+    known_hosts: Option<String>,
+
+    /// allow sign in to other ssh server whose host matches the pattern, can be repeated (default: local ssh server only, example: *.internal or 10.0.0.*:2222)
+    #[argh(option)]
+    allow_host: Vec<String>,
+
+    /// allow sign in with the identities of the ssh agent (SSH_AUTH_SOCK) that this app runs with, only for a username that the browser's client certificate is mapped to by '--client-cert-user' without wildcard (default: false)
+    #[argh(switch)]
+    ssh_agent: bool,
+
+    /// sliding window in seconds that failed sign in attempts of a username or an ip are counted in (default: 600)
+    #[argh(option)]
+    login_window: Option<u64>,
+
+    /// failed sign in attempts in the window before the username or ip is banned (default: 5)
+    #[argh(option)]
+    login_max_failures: Option<usize>,
+
+    /// seconds to wait after a failed sign in attempt, doubled by every further failure in the window (default: 5)
+    #[argh(option)]
+    login_backoff: Option<u64>,
+
+    /// seconds that a banned username or ip can't sign in (default: 900)
+    #[argh(option)]
+    login_ban: Option<u64>,
+
+    /// seconds that a signed in session lives at most, 0 means forever (default: 0)
+    #[argh(option)]
+    session_lifetime: Option<u64>,
+
+    /// seconds without any request that a signed in session expires after, 0 means never (default: 0)
+    #[argh(option)]
+    session_idle_timeout: Option<u64>,
+
+    /// seconds that a session (ssh connection and shells) waits for browser to resume it after websocket disconnected, 0 means no resume (default: 60)
+    #[argh(option)]
+    resume_grace: Option<u64>,
+
+    /// seconds that in flight transfers may take to finish after SIGTERM before sessions are closed (default: 30)
+    #[argh(option)]
+    shutdown_timeout: Option<u64>,
+
+    /// directory to keep the generated self-signed certificate in when no tls certificate is given (default: ~/.local/state/web-ssh-tool, example: /var/lib/web-ssh-tool)
+    #[argh(option)]
+    state_dir: Option<String>,
+
     /// use custom static assets and don't set this argument until you know what it means (default: bin internal static assets, example: /tmp/my_assets)
     #[argh(option)]
     assets_path: Option<String>,
 
     /// internal use and don't set this argument until you know what it means
-    #[argh(option)]
-    client: Option<String>,
+    #[argh(switch)]
+    #[serde(skip)]
+    client: bool,
+}
+
+impl Options {
+    /// Fill the settings left out with their default values, the ones told in the help.
+    fn with_defaults(mut self) -> Self {
+        if self.listen_address.is_empty() {
+            self.listen_address.push("localhost:7200".to_string());
+        }
+        self.certificate_expiry_margin.get_or_insert(14);
+        self.client_cert_mode
+            .get_or_insert_with(|| "map".to_string());
+        self.base_path.get_or_insert_with(|| "/".to_string());
+        self.log_level.get_or_insert_with(|| "info".to_string());
+        self.log_format.get_or_insert_with(|| "text".to_string());
+        self.log_max_size.get_or_insert(0);
+        self.log_rotate.get_or_insert_with(|| "never".to_string());
+        self.log_keep.get_or_insert(5);
+        self.local_ssh_port.get_or_insert_with(|| "22".to_string());
+        self.login_window.get_or_insert(600);
+        self.login_max_failures.get_or_insert(5);
+        self.login_backoff.get_or_insert(5);
+        self.login_ban.get_or_insert(900);
+        self.session_lifetime.get_or_insert(0);
+        self.session_idle_timeout.get_or_insert(0);
+        self.resume_grace.get_or_insert(60);
+        self.shutdown_timeout.get_or_insert(30);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_pattern_allows_any_port() {
+        assert!(host_matches("example.com", "example.com", "22"));
+        assert!(host_matches("example.com", "example.com", "2222"));
+        assert!(!host_matches("example.com", "www.example.com", "22"));
+    }
+
+    #[test]
+    fn host_port_pattern() {
+        assert!(host_matches("example.com:2222", "example.com", "2222"));
+        assert!(!host_matches("example.com:2222", "example.com", "22"));
+        assert!(host_matches("example.com:*", "example.com", "22"));
+    }
+
+    #[test]
+    fn wildcard_patterns() {
+        assert!(host_matches("*.internal", "db.internal", "22"));
+        assert!(!host_matches("*.internal", "db.internal.example.com", "22"));
+        assert!(host_matches("10.0.0.*:2222", "10.0.0.7", "2222"));
+        assert!(!host_matches("10.0.0.*:2222", "10.0.1.7", "2222"));
+        assert!(host_matches("*", "anything", "1"));
+    }
+
+    #[test]
+    fn ipv6_patterns() {
+        assert!(host_matches("[2001:db8::1]:2222", "2001:db8::1", "2222"));
+        assert!(!host_matches("[2001:db8::1]:2222", "2001:db8::1", "22"));
+        assert!(host_matches("[2001:db8::*]:22", "2001:db8::5", "22"));
+        // bare address allows any port
+        assert!(host_matches("2001:db8::1", "2001:db8::1", "22"));
+        assert!(host_matches("::1", "::1", "2222"));
+        assert!(!host_matches("::1", "::2", "22"));
+    }
 }
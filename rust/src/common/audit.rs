@@ -0,0 +1,239 @@
+use super::logger::session_id;
+use chrono::prelude::*;
+use serde_json::{json, Map, Value};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender};
+use std::time::Duration;
+
+enum Command {
+    Record(String),
+    Flush(SyncSender<()>),
+}
+
+/// Append-only audit trail, one JSON object a line.
+/// Records are written by master in a dedicated thread and never dropped.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    tx: Option<Sender<Command>>,
+}
+
+impl AuditLog {
+    /// The file is created readable by owner only and opened for appending.
+    pub fn open(path: &Path) -> Result<Self, std::io::Error> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        let (tx, rx) = channel();
+        std::thread::Builder::new()
+            .name("audit".to_string())
+            .spawn(move || {
+                while let Ok(command) = rx.recv() {
+                    match command {
+                        Command::Record(line) => {
+                            if let Err(e) = file.write_all(line.as_bytes()) {
+                                eprintln!(
+                                    "Failed to write audit record ({:?}): {}",
+                                    e,
+                                    line.trim_end()
+                                );
+                            }
+                        }
+                        Command::Flush(done) => {
+                            let _ = file.sync_all();
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            tx: Some(tx),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// `time` and `event` come first, then the fields of the object.
+    pub fn record(&self, event: &str, fields: Value) {
+        let tx = match &self.tx {
+            Some(tx) => tx,
+            None => return,
+        };
+        let mut line = format!(
+            "{{\"time\":{},\"event\":{}",
+            Value::from(Utc::now().format("%+").to_string()),
+            Value::from(event)
+        );
+        if let Value::Object(fields) = fields {
+            for (key, value) in fields {
+                line.push_str(&format!(",{}:{}", Value::from(key), value));
+            }
+        }
+        line.push_str("}\n");
+        let _ = tx.send(Command::Record(line));
+    }
+
+    /// Wait (at most a few seconds) for records to reach the disk, before the process exits.
+    pub fn flush(&self) {
+        if let Some(tx) = &self.tx {
+            let (done_tx, done_rx) = sync_channel(1);
+            if tx.send(Command::Flush(done_tx)).is_ok() {
+                let _ = done_rx.recv_timeout(Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "File({})", path.display()),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+/// Audit records on behalf of a signed in user,
+/// kept by the session so that resumed connections record as the same user.
+#[derive(Clone)]
+pub struct Auditor {
+    log: AuditLog,
+    session: String,
+    username: String,
+    target: String,
+}
+
+impl Auditor {
+    pub fn new(log: &AuditLog, token: &str, username: &str, target: &str) -> Self {
+        Self {
+            log: log.clone(),
+            session: session_id(token),
+            username: username.to_string(),
+            target: target.to_string(),
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn record(&self, peer: &SocketAddr, event: &str, fields: Value) {
+        if !self.log.is_enabled() {
+            return;
+        }
+        let mut record = Map::new();
+        record.insert("session".to_string(), Value::from(self.session.as_str()));
+        record.insert("username".to_string(), Value::from(self.username.as_str()));
+        record.insert("target".to_string(), Value::from(self.target.as_str()));
+        record.insert("peer".to_string(), Value::from(peer.to_string()));
+        if let Value::Object(fields) = fields {
+            record.extend(fields);
+        }
+        self.log.record(event, Value::Object(record));
+    }
+
+    /// Record of a download or upload, written with the bytes once its streams end.
+    pub fn transfer(&self, peer: &SocketAddr, event: &'static str, fields: Value) -> Transfer {
+        Transfer {
+            auditor: self.clone(),
+            peer: *peer,
+            event,
+            fields,
+        }
+    }
+}
+
+pub struct Transfer {
+    auditor: Auditor,
+    peer: SocketAddr,
+    event: &'static str,
+    fields: Value,
+}
+
+impl Transfer {
+    pub fn kind(&self) -> &'static str {
+        self.event
+    }
+
+    /// Bytes received from and sent to the browser, uploads count the received ones.
+    /// Return the bytes recorded.
+    pub fn finish(mut self, received: u64, sent: u64) -> u64 {
+        let bytes = match self.event {
+            "upload" => received,
+            _ => sent,
+        };
+        if let Value::Object(fields) = &mut self.fields {
+            fields.insert("result".to_string(), Value::from("success"));
+            fields.insert("bytes".to_string(), Value::from(bytes));
+        }
+        self.auditor.record(&self.peer, self.event, self.fields);
+        bytes
+    }
+
+    /// The transfer never began.
+    pub fn fail<T: Into<Value>>(mut self, reason: T) {
+        if let Value::Object(fields) = &mut self.fields {
+            fields.insert("result".to_string(), Value::from("failure"));
+            fields.insert("reason".to_string(), reason.into());
+        }
+        self.auditor.record(&self.peer, self.event, self.fields);
+    }
+}
+
+/// Calls of internal client that change files, recorded with their arguments.
+pub fn is_mutating_call(call: &str) -> bool {
+    matches!(
+        call,
+        "fs.unlink"
+            | "fs.rm"
+            | "fs.rename"
+            | "fs.cp"
+            | "fs.mkdir"
+            | "fs.writeFile"
+            | "fs.trash"
+            | "unzip"
+    )
+}
+
+/// Paths of file call argument (`[["dir", "name"], ...]` or `["src", ["dest"]]`),
+/// content of `fs.writeFile` is replaced by its length.
+pub fn call_fields(call: &str, argument: &Value) -> Value {
+    let join = |value: &Value| match value {
+        Value::Array(clips) => Value::from(
+            clips
+                .iter()
+                .map(|clip| clip.as_str().unwrap_or_default())
+                .collect::<PathBuf>()
+                .to_string_lossy()
+                .to_string(),
+        ),
+        Value::String(path) => Value::from(path.as_str()),
+        _ => Value::Null,
+    };
+    let array = match argument {
+        Value::Array(array) => array,
+        _ => return json!({ "call": call }),
+    };
+    match (call, array.as_slice()) {
+        ("fs.writeFile", [path, Value::String(content), ..]) => json!({
+            "call": call,
+            "path": join(path),
+            "bytes": content.len(),
+        }),
+        ("fs.rename" | "fs.cp" | "unzip", [src, dest, ..]) => json!({
+            "call": call,
+            "path": join(src),
+            "destination": join(dest),
+        }),
+        (_, [path, ..]) => json!({ "call": call, "path": join(path) }),
+        _ => json!({ "call": call }),
+    }
+}
@@ -0,0 +1,279 @@
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// `WEB_SSH_TOOL_SHUTDOWN_TIMEOUT=10` overrides `shutdown-timeout` of configuration file.
+pub const ENV_PREFIX: &str = "WEB_SSH_TOOL_";
+
+/// Read TOML (or YAML if the extension is .yaml/.yml) configuration file as a key value table.
+pub fn load(path: &str) -> Result<Map<String, Value>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let value: Value = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| e.to_string())?,
+        _ => toml::from_str(&text).map_err(|e| e.to_string())?,
+    };
+    match value {
+        Value::Object(table) => Ok(table),
+        // empty yaml document
+        Value::Null => Ok(Map::new()),
+        _ => Err("top level should be a table of settings".to_string()),
+    }
+}
+
+/// Turn `WEB_SSH_TOOL_*` environment variables into command line arguments.
+/// `defaults` (settings with default value) tells the kind of each setting:
+/// switch (false) is turned on by `true` or `1` and off by `false` or `0`, repeatable option (empty array) takes
+/// a JSON array or a single value, and the others take the value as is.
+pub fn env_args(defaults: &Map<String, Value>) -> Result<Vec<String>, String> {
+    vars_args(std::env::vars(), defaults)
+}
+
+fn vars_args<I: Iterator<Item = (String, String)>>(
+    vars: I,
+    defaults: &Map<String, Value>,
+) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut vars = vars
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect::<Vec<_>>();
+    vars.sort();
+    for (name, value) in vars {
+        let key = name[ENV_PREFIX.len()..]
+            .to_ascii_lowercase()
+            .replace('_', "-");
+        let flag = format!("--{}", key);
+        match defaults.get(&key) {
+            Some(Value::Bool(_)) => match value.as_str() {
+                "true" | "1" => args.push(flag),
+                "false" | "0" => args.push(format!("{}=false", flag)),
+                "" => {}
+                _ => return Err(format!("{} should be true or false", name)),
+            },
+            Some(Value::Array(_)) => {
+                let values = match value.trim_start().starts_with('[') {
+                    true => serde_json::from_str::<Vec<String>>(&value).map_err(|e| {
+                        format!("{} should be a JSON array of strings ({})", name, e)
+                    })?,
+                    false => vec![value],
+                };
+                for value in values {
+                    args.push(flag.clone());
+                    args.push(value);
+                }
+            }
+            Some(_) => {
+                args.push(flag);
+                args.push(value);
+            }
+            None => return Err(format!("{} isn't a known setting", name)),
+        }
+    }
+    Ok(args)
+}
+
+/// Take `--<switch>=true|1|false|0` out of arguments, that argument parser doesn't know.
+/// The ones turned on become `--<switch>`, the ones turned off are returned as settings
+/// to override what configuration file or environment variables turned on.
+pub fn switch_values(
+    args: &[String],
+    defaults: &Map<String, Value>,
+) -> Result<(Vec<String>, Map<String, Value>), String> {
+    let mut kept = Vec::with_capacity(args.len());
+    let mut off = Map::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let key = match arg.strip_prefix("--") {
+            // everything after '--' is positional
+            Some("") => {
+                kept.push(arg.clone());
+                kept.extend(args.cloned());
+                break;
+            }
+            Some(key) => key,
+            None => {
+                kept.push(arg.clone());
+                continue;
+            }
+        };
+        let (name, value) = match key.split_once('=') {
+            Some((name, value)) if matches!(defaults.get(name), Some(Value::Bool(_))) => {
+                (name, value)
+            }
+            _ => {
+                kept.push(arg.clone());
+                // value of an option may look like a switch
+                if !matches!(defaults.get(key), Some(Value::Bool(_)) | None) {
+                    kept.extend(args.next().cloned());
+                }
+                continue;
+            }
+        };
+        match value {
+            "true" | "1" => kept.push(format!("--{}", name)),
+            "false" | "0" => {
+                off.insert(name.to_string(), Value::Bool(false));
+            }
+            _ => return Err(format!("--{} should be true or false", name)),
+        }
+    }
+    Ok((kept, off))
+}
+
+/// Name options in argument parsing error by their environment variables,
+/// `--login-window` becomes `WEB_SSH_TOOL_LOGIN_WINDOW`.
+pub fn env_error(output: &str) -> String {
+    let mut parts = output.trim().split("--");
+    let mut message = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let end = part
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(part.len());
+        message.push_str(ENV_PREFIX);
+        message.push_str(&part[..end].to_ascii_uppercase().replace('-', "_"));
+        message.push_str(&part[end..]);
+    }
+    message
+}
+
+/// Settings that are set, without the empty ones (none, false and empty list).
+pub fn set_only(value: Value) -> Map<String, Value> {
+    let table = match value {
+        Value::Object(table) => table,
+        _ => return Map::new(),
+    };
+    table
+        .into_iter()
+        .filter(|(_, value)| match value {
+            Value::Null | Value::Bool(false) => false,
+            Value::Array(values) => !values.is_empty(),
+            _ => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn defaults() -> Map<String, Value> {
+        match json!({
+            "no-tls": false,
+            "trusted-proxy": [],
+            "login-window": "10m",
+            "listen-address": [],
+        }) {
+            Value::Object(table) => table,
+            _ => unreachable!(),
+        }
+    }
+
+    fn args(vars: &[(&str, &str)]) -> Result<Vec<String>, String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        vars_args(vars, &defaults())
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn env_switch() {
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_NO_TLS", "true")]).unwrap(),
+            ["--no-tls"]
+        );
+        assert_eq!(args(&[("WEB_SSH_TOOL_NO_TLS", "1")]).unwrap(), ["--no-tls"]);
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_NO_TLS", "false")]).unwrap(),
+            ["--no-tls=false"]
+        );
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_NO_TLS", "0")]).unwrap(),
+            ["--no-tls=false"]
+        );
+        assert!(args(&[("WEB_SSH_TOOL_NO_TLS", "")]).unwrap().is_empty());
+        assert!(args(&[("WEB_SSH_TOOL_NO_TLS", "yes")]).is_err());
+    }
+
+    #[test]
+    fn env_repeatable_option() {
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_TRUSTED_PROXY", r#"["10.0.0.0/8", "::1"]"#)]).unwrap(),
+            ["--trusted-proxy", "10.0.0.0/8", "--trusted-proxy", "::1"]
+        );
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_TRUSTED_PROXY", "10.0.0.0/8")]).unwrap(),
+            ["--trusted-proxy", "10.0.0.0/8"]
+        );
+        assert!(args(&[("WEB_SSH_TOOL_TRUSTED_PROXY", "[10.0.0.0/8")]).is_err());
+    }
+
+    #[test]
+    fn env_plain_option() {
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_LOGIN_WINDOW", "5m")]).unwrap(),
+            ["--login-window", "5m"]
+        );
+        // value is passed as is, even if it looks like a JSON array
+        assert_eq!(
+            args(&[("WEB_SSH_TOOL_LOGIN_WINDOW", "[5m]")]).unwrap(),
+            ["--login-window", "[5m]"]
+        );
+    }
+
+    #[test]
+    fn env_other_variables() {
+        assert!(args(&[("HOME", "/root"), ("WEB_SSH_TOOLS", "x")])
+            .unwrap()
+            .is_empty());
+        assert!(args(&[("WEB_SSH_TOOL_NO_SUCH_SETTING", "1")]).is_err());
+    }
+
+    #[test]
+    fn env_in_name_order() {
+        assert_eq!(
+            args(&[
+                ("WEB_SSH_TOOL_NO_TLS", "1"),
+                ("WEB_SSH_TOOL_LOGIN_WINDOW", "5m"),
+            ])
+            .unwrap(),
+            ["--login-window", "5m", "--no-tls"]
+        );
+    }
+
+    #[test]
+    fn switch_on_and_off() {
+        let (kept, off) = switch_values(
+            &strings(&["--no-tls=1", "--login-window", "5m"]),
+            &defaults(),
+        )
+        .unwrap();
+        assert_eq!(kept, ["--no-tls", "--login-window", "5m"]);
+        assert!(off.is_empty());
+        let (kept, off) = switch_values(&strings(&["--no-tls=false"]), &defaults()).unwrap();
+        assert!(kept.is_empty());
+        assert_eq!(off.get("no-tls"), Some(&Value::Bool(false)));
+        assert!(switch_values(&strings(&["--no-tls=maybe"]), &defaults()).is_err());
+    }
+
+    #[test]
+    fn switch_like_values_are_kept() {
+        let args = strings(&["--login-window", "--no-tls=0", "--", "--no-tls=0"]);
+        let (kept, off) = switch_values(&args, &defaults()).unwrap();
+        assert_eq!(kept, args);
+        assert!(off.is_empty());
+        // '=' form of other options and unknown ones are left to argument parser
+        let args = strings(&["--login-window=5m", "--unknown=1"]);
+        assert_eq!(switch_values(&args, &defaults()).unwrap().0, args);
+    }
+
+    #[test]
+    fn env_error_names() {
+        assert_eq!(
+            env_error("Unrecognized argument: --login-window\n"),
+            "Unrecognized argument: WEB_SSH_TOOL_LOGIN_WINDOW"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use super::ip_filter::Cidr;
+use hyper::{header, HeaderMap};
+use std::net::{IpAddr, SocketAddr};
+
+/// Real client address of request coming through trusted reverse proxies.
+/// Hops in `Forwarded` (or `X-Forwarded-For` if absent) are walked from the nearest one,
+/// the first address not of a trusted proxy is the client. Peer itself if it isn't trusted,
+/// port is 0 if the proxy doesn't tell.
+pub fn client_address(
+    trusted_proxies: &[Cidr],
+    peer: &SocketAddr,
+    headers: &HeaderMap,
+) -> SocketAddr {
+    let is_trusted =
+        |addr: &SocketAddr| trusted_proxies.iter().any(|cidr| cidr.contains(&addr.ip()));
+    let hops = match headers.contains_key(header::FORWARDED) {
+        true => forwarded_hops(headers),
+        false => x_forwarded_for_hops(headers),
+    };
+    let mut client = *peer;
+    for hop in hops.iter().rev() {
+        if !is_trusted(&client) {
+            break;
+        }
+        match parse_node(hop) {
+            Some(addr) => client = addr,
+            // "unknown" or obfuscated node, the proxy is the farthest known
+            None => break,
+        }
+    }
+    client
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim())
+}
+
+/// `for` parameter of each element, e.g. `for=192.0.2.60;proto=https, for="[2001:db8::1]:4711"`.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<String> {
+    header_values(headers, header::FORWARDED.as_str())
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, value)| value.trim().trim_matches('"').to_string())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Vec<String> {
+    header_values(headers, "x-forwarded-for")
+        .map(|value| value.to_string())
+        .collect()
+}
+
+/// Whether the nearest proxy tells that browser came with https, by `proto` of the last
+/// `Forwarded` element or else the last `X-Forwarded-Proto` value.
+pub fn is_forwarded_https(headers: &HeaderMap) -> bool {
+    let proto = match headers.contains_key(header::FORWARDED) {
+        true => header_values(headers, header::FORWARDED.as_str())
+            .last()
+            .and_then(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("proto"))
+                    .map(|(_, value)| value.trim().trim_matches('"'))
+            }),
+        false => header_values(headers, "x-forwarded-proto").last(),
+    };
+    matches!(proto, Some(proto) if proto.eq_ignore_ascii_case("https"))
+}
+
+/// `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::1` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = node.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn trusted(list: &[&str]) -> Vec<Cidr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn headers(entries: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_client() {
+        let headers = headers(&[("x-forwarded-for", "192.0.2.60")]);
+        let client = client_address(&trusted(&["10.0.0.1"]), &addr("203.0.113.9:5000"), &headers);
+        assert_eq!(client, addr("203.0.113.9:5000"));
+    }
+
+    #[test]
+    fn trusted_peer_without_header_is_client() {
+        let client = client_address(
+            &trusted(&["127.0.0.1"]),
+            &addr("127.0.0.1:5000"),
+            &HeaderMap::new(),
+        );
+        assert_eq!(client, addr("127.0.0.1:5000"));
+    }
+
+    #[test]
+    fn walks_hops_from_nearest_until_untrusted() {
+        // client, then a proxy that isn't trusted, then two trusted ones
+        let headers = headers(&[("x-forwarded-for", "192.0.2.60, 198.51.100.7, 10.0.0.2")]);
+        let client = client_address(&trusted(&["10.0.0.0/24"]), &addr("10.0.0.1:5000"), &headers);
+        assert_eq!(client, addr("198.51.100.7:0"));
+    }
+
+    #[test]
+    fn forged_hops_before_untrusted_are_ignored() {
+        let headers = headers(&[("x-forwarded-for", "127.0.0.1, 203.0.113.9")]);
+        let client = client_address(&trusted(&["127.0.0.1"]), &addr("127.0.0.1:5000"), &headers);
+        assert_eq!(client, addr("203.0.113.9:0"));
+    }
+
+    #[test]
+    fn repeated_headers_are_one_list() {
+        let headers = headers(&[
+            ("x-forwarded-for", "192.0.2.60"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+        let client = client_address(&trusted(&["10.0.0.0/24"]), &addr("10.0.0.1:5000"), &headers);
+        assert_eq!(client, addr("192.0.2.60:0"));
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_for() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                r#"for=192.0.2.60;proto=https, for="[2001:db8::1]:4711""#,
+            ),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+        let client = client_address(&trusted(&["127.0.0.1"]), &addr("127.0.0.1:5000"), &headers);
+        assert_eq!(client, addr("[2001:db8::1]:4711"));
+
+        let client = client_address(
+            &trusted(&["127.0.0.1", "2001:db8::/32"]),
+            &addr("127.0.0.1:5000"),
+            &headers,
+        );
+        assert_eq!(client, addr("192.0.2.60:0"));
+    }
+
+    #[test]
+    fn unknown_hop_stops_at_the_proxy() {
+        let headers = headers(&[("forwarded", "for=192.0.2.60, for=unknown")]);
+        let client = client_address(&trusted(&["127.0.0.1"]), &addr("127.0.0.1:5000"), &headers);
+        assert_eq!(client, addr("127.0.0.1:5000"));
+    }
+
+    #[test]
+    fn forwarded_https_of_nearest_proxy() {
+        assert!(is_forwarded_https(&headers(&[(
+            "forwarded",
+            "for=192.0.2.60;proto=http, for=10.0.0.2;proto=https"
+        )])));
+        assert!(!is_forwarded_https(&headers(&[(
+            "forwarded",
+            "for=192.0.2.60;proto=https, for=10.0.0.2;proto=http"
+        )])));
+        assert!(is_forwarded_https(&headers(&[(
+            "x-forwarded-proto",
+            "HTTPS"
+        )])));
+        assert!(!is_forwarded_https(&headers(&[(
+            "x-forwarded-proto",
+            "http"
+        )])));
+        assert!(!is_forwarded_https(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::prelude::*;
+use futures::{lock::Mutex, Future};
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Result of ssh probe is reused for a while, so that frequent checks don't flood sshd.
+const SSH_PROBE_TTL: Duration = Duration::from_secs(5);
+
+/// One item of `/healthz` or `/readyz`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn pass<T: Into<String>>(detail: T) -> Self {
+        Self {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn fail<T: Into<String>>(detail: T) -> Self {
+        Self {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+enum Certificate {
+    #[default]
+    NotLoaded,
+    BuiltIn,                       // bundled with the bin, nothing for the operator to renew
+    Loaded(String, DateTime<Utc>), // subject and expiry of the one that expires first
+}
+
+/// What master knows of itself for health and readiness checks.
+#[derive(Debug, Default)]
+pub struct Health {
+    listening: AtomicUsize,
+    certificate: std::sync::Mutex<Certificate>,
+    ssh_probe: Mutex<Option<(Instant, Check)>>,
+}
+
+impl Health {
+    pub fn listener_started(&self) {
+        self.listening.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Listener stops accepting once draining.
+    pub fn listener_stopped(&self) {
+        self.listening.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn listener(&self, expected: usize) -> Check {
+        let listening = self.listening.load(Ordering::Relaxed);
+        let detail = format!("{} of {} listen address(es) accepting", listening, expected);
+        match listening == expected && expected > 0 {
+            true => Check::pass(detail),
+            false => Check::fail(detail),
+        }
+    }
+
+    /// Called whenever certificates are (re)loaded, with the one that expires first,
+    /// none if only the built-in certificate serves.
+    pub fn certificate_loaded(&self, first_expiry: Option<(String, i64)>) {
+        let certificate = match first_expiry {
+            Some((name, not_after)) => match Utc.timestamp_opt(not_after, 0).single() {
+                Some(time) => Certificate::Loaded(name, time),
+                None => Certificate::NotLoaded,
+            },
+            None => Certificate::BuiltIn,
+        };
+        *self.certificate.lock().unwrap_or_else(|e| e.into_inner()) = certificate;
+    }
+
+    pub fn certificate(&self, no_tls: bool, margin: Duration) -> Check {
+        if no_tls {
+            return Check::pass("tls disabled");
+        }
+        let certificate = self.certificate.lock().unwrap_or_else(|e| e.into_inner());
+        let (name, not_after) = match &*certificate {
+            Certificate::Loaded(name, not_after) => (name, not_after),
+            Certificate::BuiltIn => return Check::pass("built-in certificate, not for production"),
+            Certificate::NotLoaded => return Check::fail("certificate not loaded"),
+        };
+        let left = *not_after - Utc::now();
+        let detail = format!(
+            "certificate ({}) expires at {} ({} day(s) left)",
+            name,
+            not_after.format("%+"),
+            left.num_days()
+        );
+        match left.to_std() {
+            Ok(left) if left > margin => Check::pass(detail),
+            _ => Check::fail(detail),
+        }
+    }
+
+    /// Last result of ssh probe if it is fresh, otherwise run the probe.
+    /// Concurrent checks wait for the same probe.
+    pub async fn ssh<F: Future<Output = Check>>(&self, probe: F) -> Check {
+        let mut last = self.ssh_probe.lock().await;
+        if let Some((at, check)) = &*last {
+            if at.elapsed() < SSH_PROBE_TTL {
+                return check.clone();
+            }
+        }
+        let check = probe.await;
+        *last = Some((Instant::now(), check.clone()));
+        check
+    }
+}
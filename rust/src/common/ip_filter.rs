@@ -0,0 +1,203 @@
+use std::net::IpAddr;
+
+/// Network in CIDR notation, a bare address is a network of itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // ipv4 client may come from dual stack socket as ipv4-mapped ipv6 address
+        let ip = match ip {
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => *ip,
+            },
+            IpAddr::V4(_) => *ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || CidrParseError(s.to_string());
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| error())?;
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| error())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(error());
+        }
+        Ok(Cidr { address, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// Deny list always wins. Empty allow list allows every address that is not denied.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, CidrParseError> {
+        Ok(IpFilter {
+            allow: allow.iter().map(|s| s.parse()).collect::<Result<_, _>>()?,
+            deny: deny.iter().map(|s| s.parse()).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_allowed(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+impl std::fmt::Display for IpFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |list: &Vec<Cidr>| {
+            list.iter()
+                .map(|cidr| cidr.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "allow [{}] deny [{}]",
+            join(&self.allow),
+            join(&self.deny)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CidrParseError(String);
+
+impl std::fmt::Display for CidrParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid address or CIDR ({})", self.0)
+    }
+}
+impl std::error::Error for CidrParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn ip_filter(allow: &[&str], deny: &[&str]) -> IpFilter {
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        IpFilter::new(&strings(allow), &strings(deny)).unwrap()
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let cidr: Cidr = "10.8.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&ip("10.8.0.1")));
+        assert!(cidr.contains(&ip("10.8.255.255")));
+        assert!(!cidr.contains(&ip("10.9.0.1")));
+        assert!(!cidr.contains(&ip("fd00::1")));
+
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains(&ip("fd12:3456::1")));
+        assert!(!cidr.contains(&ip("fe80::1")));
+        assert!(!cidr.contains(&ip("10.8.0.1")));
+    }
+
+    #[test]
+    fn cidr_bare_address_and_zero_prefix() {
+        let cidr: Cidr = "192.0.2.7".parse().unwrap();
+        assert_eq!(cidr.to_string(), "192.0.2.7/32");
+        assert!(cidr.contains(&ip("192.0.2.7")));
+        assert!(!cidr.contains(&ip("192.0.2.8")));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&ip("203.0.113.1")));
+        let any: Cidr = "::/0".parse().unwrap();
+        assert!(any.contains(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn cidr_parse_error() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn cidr_matches_ipv4_mapped_address() {
+        let cidr: Cidr = "10.8.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&ip("::ffff:10.8.1.2")));
+        assert!(!cidr.contains(&ip("::ffff:10.9.1.2")));
+    }
+
+    #[test]
+    fn empty_filter_allows_any() {
+        let filter = ip_filter(&[], &[]);
+        assert!(filter.is_allowed(&ip("203.0.113.1")));
+        assert!(filter.is_allowed(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allow_list_only_allows_listed() {
+        let filter = ip_filter(&["10.8.0.0/16", "fd00::/8"], &[]);
+        assert!(filter.is_allowed(&ip("10.8.0.1")));
+        assert!(filter.is_allowed(&ip("::ffff:10.8.0.1")));
+        assert!(filter.is_allowed(&ip("fd00::1")));
+        assert!(!filter.is_allowed(&ip("10.9.0.1")));
+        assert!(!filter.is_allowed(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = ip_filter(&["10.8.0.0/16"], &["10.8.0.100/32"]);
+        assert!(filter.is_allowed(&ip("10.8.0.99")));
+        assert!(!filter.is_allowed(&ip("10.8.0.100")));
+        assert!(!filter.is_allowed(&ip("::ffff:10.8.0.100")));
+
+        let filter = ip_filter(&[], &["127.0.0.1"]);
+        assert!(!filter.is_allowed(&ip("127.0.0.1")));
+        assert!(filter.is_allowed(&ip("127.0.0.2")));
+    }
+}
@@ -0,0 +1,333 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::hmac;
+use russh_keys::{key::PublicKey, load_public_key, parse_public_key_base64};
+use std::path::Path;
+
+use super::wildcard_match;
+
+// host names that are treated as this machine
+const LOCAL_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+#[derive(Debug, Default)]
+pub struct KnownHosts {
+    local: Vec<PublicKey>,                       // host keys of this machine
+    entries: Vec<(Vec<HostPattern>, PublicKey)>, // known_hosts file entries
+    skipped: Vec<String>,                        // lines not loaded and why
+}
+
+/// Host pattern of a known_hosts entry, as sshd(8) describes them.
+#[derive(Debug)]
+enum HostPattern {
+    Wildcard(String),
+    Negated(String), // '!pattern', the entry doesn't apply to hosts it matches
+    Hashed(Vec<u8>, Vec<u8>), // '|1|salt|hash', HMAC-SHA1 of the host name keyed by salt
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        if let Some(hashed) = pattern.strip_prefix("|1|") {
+            let decode = |s: &str| STANDARD.decode(s).ok();
+            return match hashed.split_once('|') {
+                Some((salt, hash)) => match (decode(salt), decode(hash)) {
+                    (Some(salt), Some(hash)) => Ok(HostPattern::Hashed(salt, hash)),
+                    _ => Err(format!("hashed host name format error ({})", pattern)),
+                },
+                None => Err(format!("hashed host name format error ({})", pattern)),
+            };
+        }
+        if pattern.starts_with('|') {
+            return Err(format!("unsupported hashed host name ({})", pattern));
+        }
+        match pattern.strip_prefix('!') {
+            Some(negated) => Ok(HostPattern::Negated(negated.to_string())),
+            None => Ok(HostPattern::Wildcard(pattern.to_string())),
+        }
+    }
+
+    fn matches(&self, host_port: &str) -> bool {
+        match self {
+            HostPattern::Wildcard(pattern) | HostPattern::Negated(pattern) => {
+                wildcard_match(pattern, host_port)
+            }
+            HostPattern::Hashed(salt, hash) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, salt);
+                hmac::verify(&key, host_port.as_bytes(), hash).is_ok()
+            }
+        }
+    }
+}
+
+/// Entry applies if a pattern matches the host and no negated one does.
+fn entry_matches(patterns: &[HostPattern], host_port: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match (pattern, pattern.matches(host_port)) {
+            (HostPattern::Negated(_), true) => return false,
+            (_, true) => matched = true,
+            _ => {}
+        }
+    }
+    matched
+}
+
+/// Host patterns and key of a known_hosts line, none for blank and comment lines.
+fn parse_line(line: &str) -> Result<Option<(Vec<HostPattern>, PublicKey)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    if line.starts_with('@') {
+        return Err("'@cert-authority' and '@revoked' markers are not supported".to_string());
+    }
+    let mut split = line.split_whitespace();
+    let (patterns, key) = match (split.next(), split.next(), split.next()) {
+        (Some(patterns), Some(_), Some(key)) => (patterns, key),
+        _ => return Err("format error".to_string()),
+    };
+    let key = parse_public_key_base64(key).map_err(|e| format!("key format error ({})", e))?;
+    let patterns = patterns
+        .split(',')
+        .map(HostPattern::parse)
+        .collect::<Result<_, _>>()?;
+    Ok(Some((patterns, key)))
+}
+
+impl KnownHosts {
+    /// Load this machine's sshd host keys and the entries of a known_hosts file if given.
+    /// Lines with `@cert-authority`/`@revoked` markers or in bad format are skipped, see `skipped`.
+    pub fn load(known_hosts: &Option<String>) -> Result<Self, std::io::Error> {
+        let mut hosts = KnownHosts::default();
+        let local = load_local_host_keys(&mut hosts.local);
+        match known_hosts {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)?;
+                for (number, line) in content.lines().enumerate() {
+                    match parse_line(line) {
+                        Ok(Some(entry)) => hosts.entries.push(entry),
+                        Ok(None) => {}
+                        Err(e) => hosts
+                            .skipped
+                            .push(format!("{}:{}: {}", path, number + 1, e)),
+                    }
+                }
+            }
+            None => local?,
+        }
+        Ok(hosts)
+    }
+
+    /// Lines of the known_hosts file that are not loaded, with the reason.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.local.is_empty() && self.entries.is_empty()
+    }
+
+    pub fn verify(&self, host: &str, port: &str, key: &PublicKey) -> Result<(), HostKeyError> {
+        let mut known = false;
+        if LOCAL_HOSTS.contains(&host) {
+            if self.local.contains(key) {
+                return Ok(());
+            }
+            known = !self.local.is_empty();
+        }
+        let host_port = match port {
+            "22" => host.to_string(),
+            port => format!("[{}]:{}", host, port),
+        };
+        for (patterns, k) in self.entries.iter() {
+            if entry_matches(patterns, &host_port) {
+                if k == key {
+                    return Ok(());
+                }
+                known = true;
+            }
+        }
+        match known {
+            true => Err(HostKeyError::Mismatch(key.fingerprint())),
+            false => Err(HostKeyError::Unknown(key.fingerprint())),
+        }
+    }
+}
+
+impl std::fmt::Display for KnownHosts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} local host key(s), {} known hosts entry(s)",
+            self.local.len(),
+            self.entries.len()
+        )
+    }
+}
+
+fn load_local_host_keys(keys: &mut Vec<PublicKey>) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(host_keys_dir())? {
+        let path = entry?.path();
+        let is_host_key = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.starts_with("ssh_host_") && name.ends_with("_key.pub"),
+            None => false,
+        };
+        // unsupported key types can't be negotiated either, just skip them
+        if let (true, Ok(key)) = (is_host_key, load_public_key(&path)) {
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn host_keys_dir() -> &'static Path {
+    Path::new("/etc/ssh")
+}
+
+#[cfg(target_os = "windows")]
+fn host_keys_dir() -> &'static Path {
+    Path::new("C:\\ProgramData\\ssh")
+}
+
+#[derive(Debug, Clone)]
+pub enum HostKeyError {
+    Mismatch(String),
+    Unknown(String),
+}
+
+impl std::fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostKeyError::Mismatch(fingerprint) => write!(
+                f,
+                "host key SHA256:{} doesn't match the known host key",
+                fingerprint
+            ),
+            HostKeyError::Unknown(fingerprint) => {
+                write!(f, "host key SHA256:{} is unknown", fingerprint)
+            }
+        }
+    }
+}
+impl std::error::Error for HostKeyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIPcO9GKF5sNbT3Q7DJhyHbmNbtzABgogV2qxSZe6K9iq";
+    const OTHER_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIGiCwlv4H+IPV7H0NkxYj+pxY6SQ08+PoHjm2s9U+1Xz";
+
+    fn known_hosts(lines: &[&str]) -> KnownHosts {
+        KnownHosts {
+            entries: lines
+                .iter()
+                .map(|line| parse_line(line).unwrap().unwrap())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn key(key: &str) -> PublicKey {
+        parse_public_key_base64(key).unwrap()
+    }
+
+    fn matches(patterns: &str, host_port: &str) -> bool {
+        let line = format!("{} ssh-ed25519 {}", patterns, KEY);
+        let (patterns, _) = parse_line(&line).unwrap().unwrap();
+        entry_matches(&patterns, host_port)
+    }
+
+    #[test]
+    fn blank_and_comment_lines() {
+        assert!(parse_line("").unwrap().is_none());
+        assert!(parse_line("   ").unwrap().is_none());
+        assert!(parse_line("# example.com ssh-ed25519 AAAA")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn unsupported_lines() {
+        let revoked = format!("@revoked * ssh-ed25519 {}", KEY);
+        assert!(parse_line(&revoked).is_err());
+        let ca = format!("@cert-authority *.example.com ssh-ed25519 {}", KEY);
+        assert!(parse_line(&ca).is_err());
+        assert!(parse_line("example.com ssh-ed25519").is_err());
+        assert!(parse_line("example.com ssh-ed25519 not-a-key").is_err());
+        let malformed = format!("|1|AAEC ssh-ed25519 {}", KEY);
+        assert!(parse_line(&malformed).is_err());
+        let bad_base64 = format!("|1|!!|?? ssh-ed25519 {}", KEY);
+        assert!(parse_line(&bad_base64).is_err());
+        let unknown_hash = format!("|2|AAEC|AAEC ssh-ed25519 {}", KEY);
+        assert!(parse_line(&unknown_hash).is_err());
+    }
+
+    #[test]
+    fn wildcard_patterns() {
+        assert!(matches("example.com", "example.com"));
+        assert!(!matches("example.com", "www.example.com"));
+        assert!(matches("*.example.com", "www.example.com"));
+        assert!(matches("host?", "host1"));
+        assert!(!matches("host?", "host10"));
+        assert!(matches("example.org,example.com", "example.com"));
+    }
+
+    #[test]
+    fn non_default_port() {
+        assert!(matches("[example.com]:2222", "[example.com]:2222"));
+        assert!(!matches("[example.com]:2222", "example.com"));
+        assert!(!matches("example.com", "[example.com]:2222"));
+        assert!(matches("[example.com]:*", "[example.com]:2222"));
+    }
+
+    #[test]
+    fn negated_patterns() {
+        assert!(matches(
+            "*.example.com,!bastion.example.com",
+            "www.example.com"
+        ));
+        assert!(!matches(
+            "*.example.com,!bastion.example.com",
+            "bastion.example.com"
+        ));
+        // negation alone never makes an entry apply
+        assert!(!matches("!bastion.example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn hashed_patterns() {
+        // from ssh-keygen -H
+        let hashed = "|1|RBvcIIAVXD60nJT6/zw6WC03xyE=|Rm+Ur6WkHfH8Hnx8A1ZVvBPAitA=";
+        assert!(matches(hashed, "example.com"));
+        assert!(!matches(hashed, "example.org"));
+        let salt = "AAECAwQFBgcICQoLDA0ODxAREhM=";
+        let hashed = format!("|1|{}|nnUK16ANsXd3hL31YfAkGOluSjU=", salt);
+        assert!(matches(&hashed, "example.com"));
+        assert!(!matches(&hashed, "[example.com]:2222"));
+        let hashed = format!("|1|{}|Wgcx+Fm+LmaWwC7rQ80eIf2uHe0=", salt);
+        assert!(matches(&hashed, "[example.com]:2222"));
+        assert!(!matches(&hashed, "example.com"));
+    }
+
+    #[test]
+    fn verify_key() {
+        let hosts = known_hosts(&[
+            &format!("example.com ssh-ed25519 {}", KEY),
+            &format!("[example.com]:2222 ssh-ed25519 {}", OTHER_KEY),
+        ]);
+        assert!(hosts.verify("example.com", "22", &key(KEY)).is_ok());
+        assert!(hosts.verify("example.com", "2222", &key(OTHER_KEY)).is_ok());
+        assert!(matches!(
+            hosts.verify("example.com", "22", &key(OTHER_KEY)),
+            Err(HostKeyError::Mismatch(_))
+        ));
+        assert!(matches!(
+            hosts.verify("example.org", "22", &key(KEY)),
+            Err(HostKeyError::Unknown(_))
+        ));
+    }
+}
@@ -0,0 +1,498 @@
+use chrono::prelude::*;
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Lines waiting for the writer thread, later ones are dropped (and counted) if it falls behind.
+const QUEUE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" | "warning" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!(
+                "unknown level {:?}, expect debug, info, warn or error",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `2023-01-01T00:00:00+00:00 [INFO]  message key=value`
+    Text,
+    /// One JSON object a line with `time`, `level`, `message` and the event fields.
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format {:?}, expect text or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "never" => Ok(Period::Never),
+            "hourly" => Ok(Period::Hourly),
+            "daily" => Ok(Period::Daily),
+            _ => Err(format!(
+                "unknown period {:?}, expect never, hourly or daily",
+                s
+            )),
+        }
+    }
+}
+
+/// Log file is renamed to `<path>.1` (older ones shift to `<path>.2` and so on, at most `keep`)
+/// when it grows over `max_size` bytes (0 for no limit) or a new period begins.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub period: Period,
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Output {
+    None,
+    /// Errors and warnings to stderr, the others to stdout.
+    Stdio,
+    Stderr,
+    File(PathBuf, Rotation),
+}
+
+enum Command {
+    Line(String, bool),
+    Flush(SyncSender<()>),
+}
+
+/// Leveled logger whose output is written by a dedicated thread,
+/// so that logging never blocks the async runtime.
+pub struct Logger {
+    level: Level,
+    format: Format,
+    output: Output,
+    tx: Option<SyncSender<Command>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Logger {
+    pub fn new(output: Output, level: Level, format: Format) -> Result<Self, std::io::Error> {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let sink = match &output {
+            Output::None => None,
+            Output::Stdio | Output::Stderr => Some(Sink::Stdio),
+            Output::File(path, rotation) => Some(Sink::File(LogFile::open(path, *rotation)?)),
+        };
+        let tx = match sink {
+            Some(sink) => {
+                let (tx, rx) = sync_channel(QUEUE_SIZE);
+                let writer = Writer {
+                    sink,
+                    format,
+                    dropped: dropped.clone(),
+                };
+                std::thread::Builder::new()
+                    .name("logger".to_string())
+                    .spawn(move || writer.run(rx))?;
+                Some(tx)
+            }
+            None => None,
+        };
+        Ok(Self {
+            level,
+            format,
+            output,
+            tx,
+            dropped,
+        })
+    }
+
+    pub fn is_enabled(&self, level: Level) -> bool {
+        self.tx.is_some() && level >= self.level
+    }
+
+    pub fn info<T: std::fmt::Display>(&self, message: T) {
+        self.event(Level::Info, message).emit()
+    }
+
+    pub fn warn<T: std::fmt::Display>(&self, message: T) {
+        self.event(Level::Warn, message).emit()
+    }
+
+    pub fn err<T: std::fmt::Display>(&self, message: T) {
+        self.event(Level::Error, message).emit()
+    }
+
+    /// Event with fields, written by `emit`:
+    /// `logger.event(Level::Info, "Signed in").field("username", name).emit()`.
+    pub fn event<T: std::fmt::Display>(&self, level: Level, message: T) -> Event<'_> {
+        let enabled = self.is_enabled(level);
+        Event {
+            logger: self,
+            level,
+            message: match enabled {
+                true => message.to_string(),
+                false => String::new(),
+            },
+            fields: Map::new(),
+            enabled,
+        }
+    }
+
+    /// Wait (at most a second) for queued lines to be written, before the process exits.
+    pub fn flush(&self) {
+        if let Some(tx) = &self.tx {
+            let (done_tx, done_rx) = sync_channel(1);
+            if tx.send(Command::Flush(done_tx)).is_ok() {
+                let _ = done_rx.recv_timeout(Duration::from_secs(1));
+            }
+        }
+    }
+
+    fn write(&self, event: &Event) {
+        let tx = match &self.tx {
+            Some(tx) => tx,
+            None => return,
+        };
+        let line = format_line(
+            self.format,
+            Utc::now(),
+            event.level,
+            &event.message,
+            &event.fields,
+        );
+        let to_stderr = match self.output {
+            Output::Stderr => true,
+            _ => event.level >= Level::Warn,
+        };
+        match tx.try_send(Command::Line(line, to_stderr)) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+impl std::fmt::Debug for Logger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.output {
+            Output::None => return write!(f, "None"),
+            Output::Stdio => write!(f, "Stdio")?,
+            Output::Stderr => write!(f, "Stderr")?,
+            Output::File(path, rotation) => write!(f, "File({}, {:?})", path.display(), rotation)?,
+        }
+        write!(f, " {} {:?}", self.level.as_str(), self.format)
+    }
+}
+
+pub struct Event<'a> {
+    logger: &'a Logger,
+    level: Level,
+    message: String,
+    fields: Map<String, Value>,
+    enabled: bool,
+}
+
+impl<'a> Event<'a> {
+    /// Text field, e.g. username, peer address or route.
+    pub fn field<T: std::fmt::Display>(mut self, key: &str, value: T) -> Self {
+        if self.enabled {
+            self.fields
+                .insert(key.to_string(), Value::String(value.to_string()));
+        }
+        self
+    }
+
+    /// Field keeps its JSON type, e.g. status code or bytes.
+    pub fn value<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
+        if self.enabled {
+            self.fields.insert(key.to_string(), value.into());
+        }
+        self
+    }
+
+    /// Duration in milliseconds as `<key>_ms`.
+    pub fn duration(self, key: &str, duration: Duration) -> Self {
+        let millis = duration.as_millis() as u64;
+        self.value(&format!("{}_ms", key), millis)
+    }
+
+    pub fn emit(self) {
+        if self.enabled {
+            self.logger.write(&self);
+        }
+    }
+}
+
+/// Short id of session token, tells sessions apart in logs without leaking the token.
+pub fn session_id(token: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes());
+    digest.as_ref()[..6]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn format_line(
+    format: Format,
+    time: DateTime<Utc>,
+    level: Level,
+    message: &str,
+    fields: &Map<String, Value>,
+) -> String {
+    match format {
+        Format::Json => {
+            // time, level and message lead, the fields follow
+            let mut line = format!(
+                "{{\"time\":{},\"level\":{},\"message\":{}",
+                Value::from(time.format("%+").to_string()),
+                Value::from(level.as_str()),
+                Value::from(message),
+            );
+            for (key, value) in fields {
+                line.push_str(&format!(",{}:{}", Value::from(key.as_str()), value));
+            }
+            line.push('}');
+            line
+        }
+        Format::Text => {
+            let level = format!("[{}]", level.as_str().to_ascii_uppercase());
+            // line breaks of client supplied text would forge lines, they are escaped
+            let message = match message.contains(char::is_control) {
+                true => message.escape_debug().to_string(),
+                false => message.to_string(),
+            };
+            let mut line = format!("{} {:7} {}", time.format("%+"), level, message);
+            for (key, value) in fields {
+                let value = match value {
+                    Value::String(s)
+                        if s.is_empty()
+                            || s.contains(|c: char| {
+                                c.is_control() || matches!(c, ' ' | '"' | '=')
+                            }) =>
+                    {
+                        format!("{:?}", s)
+                    }
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                line.push_str(&format!(" {}={}", key, value));
+            }
+            line
+        }
+    }
+}
+
+enum Sink {
+    Stdio,
+    File(LogFile),
+}
+
+struct Writer {
+    sink: Sink,
+    format: Format,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Writer {
+    fn run(mut self, rx: Receiver<Command>) {
+        while let Ok(command) = rx.recv() {
+            match command {
+                Command::Line(line, to_stderr) => {
+                    let dropped = self.dropped.swap(0, Ordering::Relaxed);
+                    if dropped > 0 {
+                        let message =
+                            format!("{} log line(s) dropped, logging falls behind", dropped);
+                        let line = format_line(
+                            self.format,
+                            Utc::now(),
+                            Level::Warn,
+                            &message,
+                            &Map::new(),
+                        );
+                        self.write_line(&line, true);
+                    }
+                    self.write_line(&line, to_stderr);
+                }
+                Command::Flush(done) => {
+                    if let Sink::File(file) = &mut self.sink {
+                        file.flush();
+                    }
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &str, to_stderr: bool) {
+        match &mut self.sink {
+            Sink::Stdio if to_stderr => eprintln!("{}", line),
+            Sink::Stdio => println!("{}", line),
+            Sink::File(file) => file.write_line(line),
+        }
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    period: String,
+    has_lost_some_log: bool,
+}
+
+impl LogFile {
+    fn open(path: &Path, rotation: Rotation) -> Result<Self, std::io::Error> {
+        let file = open_append(path)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        // a file left by last run belongs to the period it was modified in
+        let modified = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file,
+            size,
+            period: period_key(rotation.period, modified),
+            has_lost_some_log: false,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let line = format!("{}\n", line);
+        let period = period_key(self.rotation.period, Utc::now());
+        let oversize = self.rotation.max_size > 0
+            && self.size > 0
+            && self.size + line.len() as u64 > self.rotation.max_size;
+        if oversize || period != self.period {
+            self.period = period;
+            if let Err(e) = self.rotate() {
+                eprintln!("Failed to rotate log file ({:?}). ", e);
+            }
+        }
+
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            if !self.has_lost_some_log {
+                eprintln!(
+                    "Failed to write log to file ({:?}). Trying reopen log file. ",
+                    e
+                );
+            }
+            if let Ok(file) = open_append(&self.path) {
+                self.file = file;
+            }
+            if let Err(e) = self.file.write_all(line.as_bytes()) {
+                if !self.has_lost_some_log {
+                    eprintln!("Failed to reopen log file and write log to file ({:?}). Output log to stdio. ", e);
+                }
+                print!("{}", line);
+                self.has_lost_some_log = true;
+                return;
+            }
+        }
+        self.size += line.len() as u64;
+        if self.has_lost_some_log {
+            self.has_lost_some_log = false;
+            self.write_line(&format_line(
+                Format::Text,
+                Utc::now(),
+                Level::Error,
+                "Some log messages have been lost. Please check out the lost messages in app stdio. ",
+                &Map::new(),
+            ));
+        }
+    }
+
+    fn rotate(&mut self) -> Result<(), std::io::Error> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.rotation.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(rotated(self.rotation.keep));
+            for n in (1..self.rotation.keep).rev() {
+                let from = rotated(n);
+                if from.exists() {
+                    std::fs::rename(&from, rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, std::io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn period_key(period: Period, time: DateTime<Utc>) -> String {
+    match period {
+        Period::Never => String::new(),
+        Period::Hourly => time.format("%Y%m%d%H").to_string(),
+        Period::Daily => time.format("%Y%m%d").to_string(),
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Records kept at most, so that attempts with ever new usernames or ips can't grow the map.
+const MAX_RECORDS: usize = 65536;
+
+/// Failed sign in attempts are counted for both username and client ip in a sliding window.
+/// Every failure asks the next attempt to wait exponentially longer,
+/// and too many failures in the window ban the username or ip for a while.
+pub struct LoginLimiter {
+    window: Duration,
+    max_failures: usize,
+    backoff: Duration,
+    ban: Duration,
+    records: HashMap<LimitKey, Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LimitKey {
+    Username(String),
+    Ip(IpAddr),
+}
+
+impl std::fmt::Display for LimitKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitKey::Username(username) => write!(f, "username({})", username),
+            LimitKey::Ip(ip) => write!(f, "ip({})", ip),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Record {
+    failures: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl Record {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while let Some(time) = self.failures.front() {
+            if now.duration_since(*time) > window {
+                self.failures.pop_front();
+            } else {
+                break;
+            }
+        }
+        if let Some(until) = self.banned_until {
+            // failures that caused the ban don't count after it
+            if until <= now {
+                self.banned_until = None;
+                self.failures.clear();
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.banned_until.is_none() && self.failures.is_empty()
+    }
+
+    fn wait(&self, now: Instant, backoff: Duration) -> Option<Duration> {
+        if let Some(until) = self.banned_until {
+            return Some(until - now);
+        }
+        let last = self.failures.back()?;
+        let exponent = (self.failures.len() - 1).min(16) as u32;
+        let until = *last + backoff * 2_u32.pow(exponent);
+        match until > now {
+            true => Some(until - now),
+            false => None,
+        }
+    }
+}
+
+impl LoginLimiter {
+    pub fn new(window: Duration, max_failures: usize, backoff: Duration, ban: Duration) -> Self {
+        Self {
+            window,
+            max_failures,
+            backoff,
+            ban,
+            records: HashMap::new(),
+        }
+    }
+
+    /// Return how long the attempt has to wait if it is not allowed now.
+    pub fn check(&mut self, username: &str, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut wait = None;
+        for key in [LimitKey::Username(username.to_string()), LimitKey::Ip(ip)] {
+            if let Some(record) = self.records.get_mut(&key) {
+                record.prune(now, self.window);
+                wait = wait.max(record.wait(now, self.backoff));
+            }
+        }
+        match wait {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a failed attempt at `now` and return the keys that just got banned.
+    pub fn fail(&mut self, username: &str, ip: IpAddr, now: Instant) -> Vec<(LimitKey, Duration)> {
+        let window = self.window;
+        self.records.retain(|_, record| {
+            record.prune(now, window);
+            !record.is_empty()
+        });
+        let mut banned = vec![];
+        for key in [LimitKey::Username(username.to_string()), LimitKey::Ip(ip)] {
+            if !self.records.contains_key(&key) && self.records.len() >= MAX_RECORDS {
+                self.evict();
+            }
+            let record = self.records.entry(key.clone()).or_default();
+            record.prune(now, window);
+            record.failures.push_back(now);
+            if record.banned_until.is_none() && record.failures.len() >= self.max_failures {
+                record.banned_until = Some(now + self.ban);
+                banned.push((key, self.ban));
+            }
+        }
+        banned
+    }
+
+    /// Drop the record least worth keeping: the one failed longest ago among those not banned,
+    /// or the ban that ends first if every record is banned.
+    fn evict(&mut self) {
+        let key = self
+            .records
+            .iter()
+            .min_by_key(|(_, record)| (record.banned_until, record.failures.back().copied()))
+            .map(|(key, _)| key.clone());
+        if let Some(key) = key {
+            self.records.remove(&key);
+        }
+    }
+
+    /// Clear the failures of the username, failures of the ip are kept.
+    pub fn succeed(&mut self, username: &str) {
+        self.records
+            .remove(&LimitKey::Username(username.to_string()));
+    }
+
+    /// Take back the failure of the ip recorded at `at` for an attempt that succeeded later,
+    /// and lift the ban if that failure caused it.
+    pub fn retract(&mut self, ip: IpAddr, at: Instant) {
+        let key = LimitKey::Ip(ip);
+        let record = match self.records.get_mut(&key) {
+            Some(record) => record,
+            None => return,
+        };
+        if let Some(index) = record.failures.iter().position(|time| *time == at) {
+            record.failures.remove(index);
+            if record.failures.len() < self.max_failures {
+                record.banned_until = None;
+            }
+        }
+        if record.is_empty() {
+            self.records.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(200);
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn limiter(max_failures: usize, ban: Duration) -> LoginLimiter {
+        LoginLimiter::new(WINDOW, max_failures, Duration::from_millis(1), ban)
+    }
+
+    #[test]
+    fn backoff_after_failure() {
+        let mut limiter = LoginLimiter::new(WINDOW, 5, Duration::from_secs(10), WINDOW);
+        assert!(limiter.check("alice", ip("192.0.2.1")).is_ok());
+        assert!(limiter
+            .fail("alice", ip("192.0.2.1"), Instant::now())
+            .is_empty());
+        // both the username and the ip wait
+        assert!(limiter.check("alice", ip("192.0.2.2")).is_err());
+        assert!(limiter.check("bob", ip("192.0.2.1")).is_err());
+        assert!(limiter.check("bob", ip("192.0.2.2")).is_ok());
+    }
+
+    #[test]
+    fn ban_after_max_failures() {
+        let mut limiter = limiter(3, Duration::from_secs(60));
+        assert!(limiter
+            .fail("alice", ip("192.0.2.1"), Instant::now())
+            .is_empty());
+        assert!(limiter
+            .fail("alice", ip("192.0.2.2"), Instant::now())
+            .is_empty());
+        let banned = limiter.fail("alice", ip("192.0.2.3"), Instant::now());
+        assert_eq!(
+            banned,
+            vec![(
+                LimitKey::Username("alice".to_string()),
+                Duration::from_secs(60)
+            )]
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        let wait = limiter.check("alice", ip("198.51.100.1")).unwrap_err();
+        assert!(wait > Duration::from_secs(50));
+        // the ips failed once each and only back off
+        assert!(limiter.check("bob", ip("192.0.2.1")).is_ok());
+    }
+
+    #[test]
+    fn ban_ends() {
+        let mut limiter = limiter(1, Duration::from_millis(20));
+        assert_eq!(
+            limiter.fail("alice", ip("192.0.2.1"), Instant::now()).len(),
+            2
+        );
+        assert!(limiter.check("alice", ip("192.0.2.1")).is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(limiter.check("alice", ip("192.0.2.1")).is_ok());
+    }
+
+    #[test]
+    fn succeed_clears_username_only() {
+        let mut limiter = LoginLimiter::new(WINDOW, 5, Duration::from_secs(10), WINDOW);
+        limiter.fail("alice", ip("192.0.2.1"), Instant::now());
+        limiter.succeed("alice");
+        assert!(limiter.check("alice", ip("192.0.2.2")).is_ok());
+        assert!(limiter.check("bob", ip("192.0.2.1")).is_err());
+    }
+
+    #[test]
+    fn failures_out_of_window_are_pruned() {
+        let mut limiter = limiter(2, Duration::from_secs(60));
+        limiter.fail("alice", ip("192.0.2.1"), Instant::now());
+        assert_eq!(limiter.records.len(), 2);
+        std::thread::sleep(WINDOW + Duration::from_millis(20));
+        // the old failure no longer counts towards the ban
+        assert!(limiter
+            .fail("alice", ip("192.0.2.1"), Instant::now())
+            .is_empty());
+        // and records of others that expired are dropped
+        limiter.fail("bob", ip("192.0.2.2"), Instant::now());
+        std::thread::sleep(WINDOW + Duration::from_millis(20));
+        limiter.fail("carol", ip("192.0.2.3"), Instant::now());
+        assert_eq!(limiter.records.len(), 2);
+        assert!(limiter
+            .records
+            .contains_key(&LimitKey::Username("carol".to_string())));
+    }
+
+    #[test]
+    fn retract_failure_of_succeeded_attempt() {
+        let mut limiter = LoginLimiter::new(WINDOW, 5, Duration::from_millis(1), WINDOW);
+        // counted before the second factor is asked, then the attempt succeeds
+        let at = Instant::now();
+        limiter.fail("alice", ip("192.0.2.1"), at);
+        limiter.succeed("alice");
+        limiter.retract(ip("192.0.2.1"), at);
+        assert!(limiter.records.is_empty());
+        // failures of other attempts from the ip are kept
+        let at = Instant::now();
+        limiter.fail("bob", ip("192.0.2.1"), Instant::now());
+        limiter.fail("alice", ip("192.0.2.1"), at);
+        limiter.succeed("alice");
+        limiter.retract(ip("192.0.2.1"), at);
+        assert_eq!(
+            limiter.records[&LimitKey::Ip(ip("192.0.2.1"))]
+                .failures
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn succeeded_attempts_never_ban_the_ip() {
+        let mut limiter = limiter(3, Duration::from_secs(60));
+        for username in ["alice", "bob", "carol", "dave"] {
+            let at = Instant::now();
+            limiter.fail(username, ip("192.0.2.1"), at);
+            limiter.succeed(username);
+            limiter.retract(ip("192.0.2.1"), at);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(limiter.check("erin", ip("192.0.2.1")).is_ok());
+    }
+
+    #[test]
+    fn retract_lifts_the_ban_it_caused() {
+        let mut limiter = limiter(2, Duration::from_secs(60));
+        limiter.fail("alice", ip("192.0.2.1"), Instant::now());
+        let at = Instant::now();
+        let banned = limiter.fail("bob", ip("192.0.2.1"), at);
+        assert_eq!(
+            banned,
+            vec![(LimitKey::Ip(ip("192.0.2.1")), Duration::from_secs(60))]
+        );
+        limiter.succeed("bob");
+        limiter.retract(ip("192.0.2.1"), at);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(limiter.check("carol", ip("192.0.2.1")).is_ok());
+        // the failure of alice still counts
+        let banned = limiter.fail("carol", ip("192.0.2.1"), Instant::now());
+        assert_eq!(banned.len(), 1);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of internal client round trip histogram buckets.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Calls of internal client with their own histogram, the others are counted as `other`
+/// so that browsers can't grow the labels.
+const CALLS: &[&str] = &[
+    "fs.access",
+    "fs.unlink",
+    "fs.rm",
+    "fs.rename",
+    "fs.exists",
+    "fs.mkdir",
+    "fs.writeFile",
+    "fs.cp",
+    "fs.trash",
+    "unzip",
+    "watch",
+    "download",
+    "upload",
+    "preview",
+];
+
+/// Counters and gauges exposed in Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    logins_succeeded: AtomicU64,
+    logins_failed: AtomicU64,
+    shells: AtomicI64,
+    watchers: AtomicI64,
+    uploaded_bytes: AtomicU64,
+    downloaded_bytes: AtomicU64,
+    previewed_bytes: AtomicU64,
+    tls_handshake_failures: AtomicU64,
+    client_calls: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn login(&self, succeeded: bool) {
+        match succeeded {
+            true => self.logins_succeeded.fetch_add(1, Ordering::Relaxed),
+            false => self.logins_failed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn shell_opened(&self) {
+        self.shells.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn shell_closed(&self) {
+        self.shells.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Watchers of internal client opened (positive) or closed (negative).
+    pub fn watchers_changed(&self, delta: i64) {
+        self.watchers.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Bytes of finished `upload`, `download` or `preview`.
+    pub fn transferred(&self, kind: &str, bytes: u64) {
+        let counter = match kind {
+            "upload" => &self.uploaded_bytes,
+            "download" => &self.downloaded_bytes,
+            "preview" => &self.previewed_bytes,
+            _ => return,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn tls_handshake_failed(&self) {
+        self.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Round trip of a call to internal client, from request sent to response received.
+    pub fn client_call(&self, call: &str, elapsed: Duration) {
+        let call = CALLS
+            .iter()
+            .find(|known| **known == call)
+            .copied()
+            .unwrap_or("other");
+        let seconds = elapsed.as_secs_f64();
+        let mut calls = self.client_calls.lock().unwrap_or_else(|e| e.into_inner());
+        let histogram = calls.entry(call).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Text exposition format, `sessions` is counted by the caller from signed in peers.
+    pub fn render(&self, sessions: usize) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            if !value.is_empty() {
+                let _ = writeln!(text, "{}", value.trim_end());
+            }
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        metric(
+            "web_ssh_tool_sessions",
+            "gauge",
+            "Signed in sessions.",
+            format!("web_ssh_tool_sessions {}", sessions),
+        );
+        metric(
+            "web_ssh_tool_shells",
+            "gauge",
+            "Open shells.",
+            format!(
+                "web_ssh_tool_shells {}",
+                self.shells.load(Ordering::Relaxed)
+            ),
+        );
+        metric(
+            "web_ssh_tool_watchers",
+            "gauge",
+            "Active file system watchers of internal clients.",
+            format!(
+                "web_ssh_tool_watchers {}",
+                self.watchers.load(Ordering::Relaxed)
+            ),
+        );
+        metric(
+            "web_ssh_tool_logins_total",
+            "counter",
+            "Sign in attempts by result.",
+            format!(
+                "web_ssh_tool_logins_total{{result=\"success\"}} {}\nweb_ssh_tool_logins_total{{result=\"failure\"}} {}",
+                load(&self.logins_succeeded),
+                load(&self.logins_failed)
+            ),
+        );
+        metric(
+            "web_ssh_tool_transferred_bytes_total",
+            "counter",
+            "Bytes of finished transfers by kind.",
+            format!(
+                "web_ssh_tool_transferred_bytes_total{{kind=\"upload\"}} {}\nweb_ssh_tool_transferred_bytes_total{{kind=\"download\"}} {}\nweb_ssh_tool_transferred_bytes_total{{kind=\"preview\"}} {}",
+                load(&self.uploaded_bytes),
+                load(&self.downloaded_bytes),
+                load(&self.previewed_bytes)
+            ),
+        );
+        metric(
+            "web_ssh_tool_tls_handshake_failures_total",
+            "counter",
+            "Failed tls handshakes.",
+            format!(
+                "web_ssh_tool_tls_handshake_failures_total {}",
+                load(&self.tls_handshake_failures)
+            ),
+        );
+
+        let name = "web_ssh_tool_client_call_duration_seconds";
+        let mut samples = String::new();
+        let calls = self.client_calls.lock().unwrap_or_else(|e| e.into_inner());
+        for (call, histogram) in calls.iter() {
+            for (bucket, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(
+                    samples,
+                    "{}_bucket{{call=\"{}\",le=\"{}\"}} {}",
+                    name, call, bound, bucket
+                );
+            }
+            let _ = writeln!(
+                samples,
+                "{}_bucket{{call=\"{}\",le=\"+Inf\"}} {}",
+                name, call, histogram.count
+            );
+            let _ = writeln!(
+                samples,
+                "{}_sum{{call=\"{}\"}} {}",
+                name, call, histogram.sum
+            );
+            let _ = writeln!(
+                samples,
+                "{}_count{{call=\"{}\"}} {}",
+                name, call, histogram.count
+            );
+        }
+        drop(calls);
+        metric(
+            name,
+            "histogram",
+            "Round trip of calls to internal client.",
+            samples,
+        );
+        text
+    }
+}
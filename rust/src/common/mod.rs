@@ -1,23 +1,32 @@
 pub mod app_config;
+pub mod audit;
 pub mod authenticate_queue;
+pub mod config_file;
+pub mod forwarded;
+pub mod health;
+pub mod ip_filter;
+pub mod known_hosts;
+pub mod listener;
+pub mod logger;
+pub mod login_limiter;
+pub mod metrics;
+pub mod pipe;
+pub mod shutdown;
 pub mod websocket_peer;
 
 use std::{collections::HashMap, sync::Arc};
 
 use bytes::Bytes;
-use futures::{
-    channel::{mpsc, oneshot},
-    lock::Mutex,
-    SinkExt,
-};
+use futures::{channel::mpsc, lock::Mutex, SinkExt};
 use http_body_util::StreamBody;
 use hyper::{body::Frame, Response};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+use crate::websocket_server::session::ParkedSessions;
+
 use self::{
-    app_config::AppConfig,
-    authenticate_queue::AuthenticateQueues,
-    websocket_peer::{ClientWebsocket, WebSocketPeer},
+    app_config::AppConfig, authenticate_queue::AuthenticateQueues, login_limiter::LoginLimiter,
+    shutdown::Shutdown, websocket_peer::WebSocketPeer,
 };
 
 pub type ResponseUnit = Result<Frame<Bytes>, Box<dyn std::error::Error + Send + Sync>>;
@@ -28,17 +37,9 @@ pub struct AppContext {
     pub app_config: Arc<AppConfig>,
     pub websocket_peers: Arc<Mutex<HashMap<String, WebSocketPeer>>>,
     pub authenticate_queues: Arc<Mutex<HashMap<String, AuthenticateQueues>>>,
-    pub suspended_clients: Arc<
-        Mutex<
-            HashMap<
-                String,
-                (
-                    oneshot::Sender<Arc<Mutex<ClientWebsocket>>>,
-                    mpsc::Sender<serde_json::Value>,
-                ),
-            >,
-        >,
-    >,
+    pub login_limiter: Arc<Mutex<LoginLimiter>>,
+    pub parked_sessions: ParkedSessions,
+    pub shutdown: Shutdown,
 }
 
 pub async fn forward_async_read_to_sender(
@@ -66,3 +67,32 @@ pub async fn forward_async_read_to_sender(
         }
     }
 }
+
+/// Match text against a pattern that may contain `*` (any sequence) and `?` (any single character).
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    backtrack = Some((bp, bt + 1));
+                    p = bp + 1;
+                    t = bt + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
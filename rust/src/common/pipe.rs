@@ -0,0 +1,443 @@
+use super::ResponseUnit;
+use bytes::Bytes;
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use hyper::{
+    body::Frame as BodyFrame,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Semaphore;
+
+// Master and internal client talk over the stdin and stdout of the ssh exec channel.
+// Every frame is [kind: u8][stream id: u64][payload length: u32][payload],
+// messages (requests, responses and events) are json and go with stream id 0,
+// file transfers are streams that the internal client opens with the id master gave in the request.
+
+/// Written by internal client before any frame, output before it (motd of login shell for example) is skipped.
+const MAGIC: &[u8] = b"\0web-ssh-tool-pipe\0";
+const MAGIC_SEARCH_LIMIT: usize = 64 * 1024;
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+const MAX_DATA: usize = 32 * 1024;
+// bytes a stream may send before the other side has consumed them, so a slow stream never blocks the others
+const WINDOW: usize = 256 * 1024;
+const BUF_SIZE: usize = 8;
+// messages and opened streams not yet taken by the receiver of events, a peer that gets that far ahead
+// floods the pipe, reader doesn't wait for the receiver as data of every stream would wait with it
+const MAX_PENDING_EVENTS: usize = 1024;
+
+const MESSAGE: u8 = 0;
+const OPEN: u8 = 1;
+const DATA: u8 = 2;
+const END: u8 = 3;
+const ACK: u8 = 4;
+
+enum Frame {
+    Message(serde_json::Value),
+    Open(u64, HeaderMap),
+    Data(u64, Bytes),
+    End(u64),
+    Ack(u64, u32),
+}
+
+impl Frame {
+    fn encode(self) -> Vec<u8> {
+        let (kind, id, payload) = match self {
+            Frame::Message(value) => (MESSAGE, 0, value.to_string().into_bytes()),
+            Frame::Open(id, headers) => (
+                OPEN,
+                id,
+                headers_to_value(&headers).to_string().into_bytes(),
+            ),
+            Frame::Data(id, data) => (DATA, id, data.to_vec()),
+            Frame::End(id) => (END, id, vec![]),
+            Frame::Ack(id, len) => (ACK, id, len.to_be_bytes().to_vec()),
+        };
+        let mut buf = Vec::with_capacity(13 + payload.len());
+        buf.push(kind);
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&payload);
+        buf
+    }
+
+    async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self, PipeError> {
+        let kind = reader.read_u8().await?;
+        let id = reader.read_u64().await?;
+        let len = reader.read_u32().await? as usize;
+        if len > MAX_FRAME {
+            return Err(PipeError::Malformed);
+        }
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).await?;
+        match kind {
+            MESSAGE => Ok(Frame::Message(serde_json::from_slice(&payload)?)),
+            OPEN => Ok(Frame::Open(
+                id,
+                value_to_headers(&serde_json::from_slice(&payload)?),
+            )),
+            DATA => Ok(Frame::Data(id, payload.into())),
+            END => Ok(Frame::End(id)),
+            ACK => match <[u8; 4]>::try_from(payload.as_slice()) {
+                Ok(len) => Ok(Frame::Ack(id, u32::from_be_bytes(len))),
+                Err(_) => Err(PipeError::Malformed),
+            },
+            _ => Err(PipeError::Malformed),
+        }
+    }
+}
+
+fn headers_to_value(headers: &HeaderMap) -> serde_json::Value {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| json!([name.as_str(), value]))
+        })
+        .collect()
+}
+
+fn value_to_headers(value: &serde_json::Value) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let serde_json::Value::Array(entries) = value {
+        for entry in entries {
+            if let (Some(name), Some(value)) = (entry[0].as_str(), entry[1].as_str()) {
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(value),
+                ) {
+                    headers.append(name, value);
+                }
+            }
+        }
+    }
+    headers
+}
+
+pub enum PipeEvent {
+    Message(serde_json::Value),
+    Open(HeaderMap, PipeStream), // stream opened by the other side
+}
+
+// std mutex because streams are ended on drop
+struct Slot {
+    incoming: Option<mpsc::UnboundedSender<Bytes>>, // none after the other side ended
+    credit: Arc<Semaphore>,
+    ended: bool, // this side ended
+}
+
+/// One end of the exec channel between master and internal client.
+#[derive(Clone)]
+pub struct Pipe {
+    frames: mpsc::Sender<Frame>,
+    streams: Arc<Mutex<HashMap<u64, Slot>>>,
+}
+
+impl Pipe {
+    /// Internal client side, announce itself to master on the writer.
+    pub async fn serve<R, W>(
+        reader: R,
+        mut writer: W,
+    ) -> Result<(Self, mpsc::Receiver<PipeEvent>), PipeError>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        writer.write_all(MAGIC).await?;
+        writer.flush().await?;
+        Ok(Self::new(BufReader::new(reader), writer))
+    }
+
+    /// Master side, wait for internal client to announce itself on the reader.
+    pub async fn connect<R, W>(
+        reader: R,
+        writer: W,
+    ) -> Result<(Self, mpsc::Receiver<PipeEvent>), PipeError>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut reader = BufReader::new(reader);
+        let mut matched = 0;
+        for _ in 0..MAGIC_SEARCH_LIMIT {
+            let byte = reader.read_u8().await?;
+            matched = match byte == MAGIC[matched] {
+                true => matched + 1,
+                false => (byte == MAGIC[0]) as usize,
+            };
+            if matched == MAGIC.len() {
+                return Ok(Self::new(reader, writer));
+            }
+        }
+        Err(PipeError::Malformed)
+    }
+
+    fn new<R, W>(reader: R, writer: W) -> (Self, mpsc::Receiver<PipeEvent>)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (frames, frames_rx) = mpsc::channel(BUF_SIZE);
+        let (events_tx, events) = mpsc::channel(MAX_PENDING_EVENTS);
+        let pipe = Self {
+            frames,
+            streams: Arc::new(Mutex::new(HashMap::new())),
+        };
+        tokio::spawn(write_frames(writer, frames_rx));
+        tokio::spawn(pipe.clone().read_frames(reader, events_tx));
+        (pipe, events)
+    }
+
+    pub async fn send_message(&self, value: serde_json::Value) -> Result<(), PipeError> {
+        self.send(Frame::Message(value)).await
+    }
+
+    /// Open the stream with the id and headers that describe it.
+    pub async fn open(&self, id: u64, headers: HeaderMap) -> Result<PipeStream, PipeError> {
+        let stream = self.register(id);
+        self.send(Frame::Open(id, headers)).await?;
+        Ok(stream)
+    }
+
+    /// Close the writer, the other side sees end of its input and stops.
+    pub fn close(&self) {
+        self.frames.clone().close_channel();
+    }
+
+    async fn send(&self, frame: Frame) -> Result<(), PipeError> {
+        self.frames
+            .clone()
+            .send(frame)
+            .await
+            .map_err(|_| PipeError::Closed)
+    }
+
+    fn register(&self, id: u64) -> PipeStream {
+        let (incoming_tx, incoming) = mpsc::unbounded();
+        let credit = Arc::new(Semaphore::new(WINDOW));
+        let slot = Slot {
+            incoming: Some(incoming_tx),
+            credit: credit.clone(),
+            ended: false,
+        };
+        self.streams.lock().unwrap().insert(id, slot);
+        PipeStream {
+            id,
+            sink: PipeSink {
+                id,
+                pipe: self.clone(),
+                credit,
+            },
+            source: PipeSource {
+                id,
+                pipe: self.clone(),
+                incoming,
+            },
+        }
+    }
+
+    // forget the stream once both sides ended
+    fn end(&self, id: u64, remote: bool) {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(slot) = streams.get_mut(&id) {
+            match remote {
+                true => slot.incoming = None,
+                false => slot.ended = true,
+            }
+            if slot.ended && slot.incoming.is_none() {
+                streams.remove(&id);
+            }
+        }
+    }
+
+    async fn read_frames<R: AsyncRead + Unpin>(
+        self,
+        mut reader: R,
+        mut events: mpsc::Sender<PipeEvent>,
+    ) {
+        while let Ok(frame) = Frame::read(&mut reader).await {
+            match frame {
+                Frame::Message(value) => {
+                    if events.try_send(PipeEvent::Message(value)).is_err() {
+                        break;
+                    }
+                }
+                Frame::Open(id, headers) => {
+                    let stream = self.register(id);
+                    if events.try_send(PipeEvent::Open(headers, stream)).is_err() {
+                        break;
+                    }
+                }
+                Frame::Data(id, data) => {
+                    let len = data.len() as u32;
+                    let delivered = match self.streams.lock().unwrap().get(&id) {
+                        Some(Slot {
+                            incoming: Some(incoming),
+                            ..
+                        }) => incoming.unbounded_send(data).is_ok(),
+                        _ => false,
+                    };
+                    if !delivered {
+                        // nobody reads the stream, let the other side finish it anyway
+                        let _ = self.send(Frame::Ack(id, len)).await;
+                    }
+                }
+                Frame::End(id) => self.end(id, true),
+                Frame::Ack(id, len) => {
+                    if let Some(slot) = self.streams.lock().unwrap().get(&id) {
+                        slot.credit.add_permits(len as usize);
+                    }
+                }
+            }
+        }
+        // the other side is gone, wake up every stream waiting on it
+        for (_, slot) in self.streams.lock().unwrap().drain() {
+            slot.credit.close();
+        }
+        self.close();
+    }
+}
+
+async fn write_frames<W: AsyncWrite + Unpin>(mut writer: W, mut frames: mpsc::Receiver<Frame>) {
+    while let Some(frame) = frames.next().await {
+        if writer.write_all(&frame.encode()).await.is_err() || writer.flush().await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Stream in both directions, each side ends its sink by dropping it.
+pub struct PipeStream {
+    pub id: u64,
+    pub sink: PipeSink,
+    pub source: PipeSource,
+}
+
+pub struct PipeSink {
+    id: u64,
+    pipe: Pipe,
+    credit: Arc<Semaphore>,
+}
+
+impl PipeSink {
+    pub async fn send(&mut self, mut data: Bytes) -> Result<(), PipeError> {
+        while !data.is_empty() {
+            let chunk = data.split_to(data.len().min(MAX_DATA));
+            self.credit
+                .acquire_many(chunk.len() as u32)
+                .await
+                .map_err(|_| PipeError::Closed)?
+                .forget();
+            self.pipe.send(Frame::Data(self.id, chunk)).await?;
+        }
+        Ok(())
+    }
+
+    /// Send everything from the receiver then end the stream.
+    pub async fn forward(mut self, mut rx: mpsc::Receiver<ResponseUnit>) -> Result<(), PipeError> {
+        while let Some(Ok(frame)) = rx.next().await {
+            if let Ok(data) = frame.into_data() {
+                self.send(data).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeSink {
+    fn drop(&mut self) {
+        self.pipe.end(self.id, false);
+        let pipe = self.pipe.clone();
+        let id = self.id;
+        tokio::spawn(async move {
+            let _ = pipe.send(Frame::End(id)).await;
+        });
+    }
+}
+
+pub struct PipeSource {
+    id: u64,
+    pipe: Pipe,
+    incoming: mpsc::UnboundedReceiver<Bytes>,
+}
+
+impl PipeSource {
+    /// Next data from the other side, none once it ended the stream.
+    pub async fn next(&mut self) -> Option<Bytes> {
+        let data = self.incoming.next().await?;
+        let _ = self.pipe.send(Frame::Ack(self.id, data.len() as u32)).await;
+        Some(data)
+    }
+
+    /// Return bytes forwarded.
+    pub async fn forward(mut self, mut tx: mpsc::Sender<ResponseUnit>) -> u64 {
+        let mut forwarded = 0;
+        while let Some(data) = self.next().await {
+            let len = data.len() as u64;
+            if tx.send(Ok(BodyFrame::data(data))).await.is_err() {
+                break; // receiver dropped, the rest is drained by the pipe
+            }
+            forwarded += len;
+        }
+        forwarded
+    }
+}
+
+impl Drop for PipeSource {
+    fn drop(&mut self) {
+        // give back the credit of data that never be read
+        self.incoming.close();
+        let mut len = 0;
+        while let Ok(Some(data)) = self.incoming.try_next() {
+            len += data.len() as u32;
+        }
+        if len > 0 {
+            let pipe = self.pipe.clone();
+            let id = self.id;
+            tokio::spawn(async move {
+                let _ = pipe.send(Frame::Ack(id, len)).await;
+            });
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PipeError {
+    Io(std::io::Error),
+    Malformed,
+    Closed,
+}
+
+impl std::fmt::Display for PipeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipeError::Io(e) => write!(f, "PipeError::Io: {}", e),
+            PipeError::Malformed => write!(f, "PipeError::Malformed"),
+            PipeError::Closed => write!(f, "PipeError::Closed"),
+        }
+    }
+}
+impl std::error::Error for PipeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<std::io::Error> for PipeError {
+    fn from(e: std::io::Error) -> Self {
+        PipeError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for PipeError {
+    fn from(_: serde_json::Error) -> Self {
+        PipeError::Malformed
+    }
+}
@@ -0,0 +1,11 @@
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Graceful shutdown goes in two steps.
+/// Draining: stop accepting, tell browsers and let in flight transfers finish.
+/// Closing: transfers finished or the deadline passed, close websockets and ssh sessions.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    pub draining: CancellationToken,
+    pub closing: CancellationToken,
+    pub sessions: TaskTracker, // websocket connections
+}
@@ -1,30 +1,44 @@
-use crate::ResponseUnit;
+use super::app_config::AppConfig;
+use super::audit::Auditor;
+use super::pipe::{Pipe, PipeStream};
 use async_trait::async_trait;
 use futures::channel::{mpsc, oneshot};
-use futures::{lock::Mutex, stream::SplitSink, SinkExt, TryFutureExt};
-use hyper::{body::Incoming, upgrade::Upgraded, Request};
+use futures::{lock::Mutex, SinkExt};
+use hyper::HeaderMap;
 use russh_keys::key;
 use serde_json::json;
-use std::{collections::HashMap, sync::Arc};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 pub struct WebSocketPeer {
-    pub client_websocket: Arc<Mutex<ClientWebsocket>>, // websocket from client
-    pub client_http: Arc<Mutex<ClientHttp>>,           // http from client
+    pub client_connection: Arc<Mutex<ClientConnection>>, // messages from client
+    pub client_http: Arc<Mutex<ClientHttp>>,             // streams from client
+    pub clock: Arc<SessionClock>,                        // lifetime and activity of session
+    pub auditor: Auditor,                                // records transfers as the signed in user
 }
 
 impl WebSocketPeer {
-    pub fn new(client_connection: Arc<Mutex<ClientWebsocket>>) -> Self {
+    pub fn new(
+        client_connection: Arc<Mutex<ClientConnection>>,
+        client_http: Arc<Mutex<ClientHttp>>,
+        clock: Arc<SessionClock>,
+        auditor: Auditor,
+    ) -> Self {
         Self {
-            client_websocket: client_connection,
-            client_http: Arc::new(Mutex::new(ClientHttp::new())),
+            client_connection,
+            client_http,
+            clock,
+            auditor,
         }
     }
 
     pub async fn disconnect(&self) {
         tokio::join!(
             async {
-                let mut conn = self.client_websocket.lock().await;
+                let mut conn = self.client_connection.lock().await;
                 conn.disconnect().await
             },
             async {
@@ -35,7 +49,81 @@ impl WebSocketPeer {
     }
 }
 
-type HttpConnection = (Request<Incoming>, mpsc::Sender<ResponseUnit>);
+pub struct SessionClock {
+    created_at: Instant,
+    last_active: Mutex<Instant>,
+    lifetime: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl SessionClock {
+    pub fn new(lifetime: Option<Duration>, idle_timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            created_at: now,
+            last_active: Mutex::new(now),
+            lifetime,
+            idle_timeout,
+        }
+    }
+
+    pub async fn touch(&self) {
+        *self.last_active.lock().await = Instant::now();
+    }
+
+    /// Return when the session will expire if no more activity (none for never),
+    /// or the reason if it has expired.
+    pub async fn deadline(&self) -> Result<Option<Instant>, SessionExpired> {
+        let now = Instant::now();
+        let lifetime = self.lifetime.map(|lifetime| self.created_at + lifetime);
+        if let Some(deadline) = lifetime {
+            if deadline <= now {
+                return Err(SessionExpired::Lifetime);
+            }
+        }
+        let idle = match self.idle_timeout {
+            Some(timeout) => Some(*self.last_active.lock().await + timeout),
+            None => None,
+        };
+        if let Some(deadline) = idle {
+            if deadline <= now {
+                return Err(SessionExpired::Idle);
+            }
+        }
+        Ok(match (lifetime, idle) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    /// Resolve once the session expired.
+    pub async fn expired(&self) -> SessionExpired {
+        loop {
+            match self.deadline().await {
+                Ok(Some(deadline)) => tokio::time::sleep_until(deadline.into()).await,
+                Ok(None) => futures::future::pending().await,
+                Err(reason) => return reason,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SessionExpired {
+    Lifetime,
+    Idle,
+}
+
+impl std::fmt::Display for SessionExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionExpired::Lifetime => write!(f, "lifetime"),
+            SessionExpired::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+type HttpConnection = (HeaderMap, PipeStream);
 pub struct ClientHttp {
     request_id: u64,
     queue: HashMap<u64, oneshot::Sender<HttpConnection>>,
@@ -72,35 +160,60 @@ impl ClientHttp {
     }
 }
 
-pub type ClientWriteChannel = SplitSink<WebSocketStream<Upgraded>, Message>;
-
 // @TODO: split ClientConnection [internal_client_stream] and [event_channel]
-pub struct ClientWebsocket {
+pub struct ClientConnection {
+    app_config: Arc<AppConfig>,
     request_id: u64,
-    internal_client_stream: ClientWriteChannel,
-    callbacks: HashMap<u64, oneshot::Sender<serde_json::Value>>,
+    internal_client_stream: Option<Pipe>, // none if internal client unavailable on target host
+    callbacks: HashMap<u64, PendingCall>,
     event_channel: mpsc::Sender<serde_json::Value>,
+    watchers: HashSet<String>, // ids of watchers opened by browser
+}
+
+struct PendingCall {
+    callback: oneshot::Sender<serde_json::Value>,
+    call: String,
+    sent_at: Instant,
+    watch: Option<WatchChange>,
 }
 
-impl ClientWebsocket {
+enum WatchChange {
+    Open(String),
+    Close(String),
+}
+
+impl ClientConnection {
     pub fn new(
+        app_config: Arc<AppConfig>,
         event_channel: mpsc::Sender<serde_json::Value>,
-        client_write_channel: ClientWriteChannel,
+        client_pipe: Option<Pipe>,
     ) -> Self {
         Self {
+            app_config,
             request_id: 0,
-            internal_client_stream: client_write_channel,
+            internal_client_stream: client_pipe,
             callbacks: HashMap::new(),
             event_channel,
+            watchers: HashSet::new(),
         }
     }
 
     async fn disconnect(&mut self) {
         let _ = futures::join!(
-            self.internal_client_stream.close(),
+            async {
+                if let Some(stream) = &self.internal_client_stream {
+                    stream.close();
+                }
+            },
             self.event_channel.close()
         );
         self.callbacks.clear();
+        let closed = self.watchers.drain().count() as i64;
+        self.app_config.metrics.watchers_changed(-closed);
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.internal_client_stream.is_some()
     }
 
     pub async fn send_request(
@@ -108,18 +221,26 @@ impl ClientWebsocket {
         request: serde_json::Value,
         callback: oneshot::Sender<serde_json::Value>,
     ) -> Result<(), &'static str> {
-        let stream = &mut self.internal_client_stream;
+        let stream = match &self.internal_client_stream {
+            Some(stream) => stream,
+            None => return Err("Internal client unavailable"),
+        };
         self.request_id += 1;
         let id = self.request_id;
-        self.callbacks.insert(id.clone(), callback);
+        let (call, watch) = describe_call(&request);
+        let pending = PendingCall {
+            callback,
+            call,
+            sent_at: Instant::now(),
+            watch,
+        };
+        self.callbacks.insert(id, pending);
         let request_object = json!({"id": id, "request": request});
-        return stream
-            .send(Message::Text(request_object.to_string()))
-            .map_err(|_| {
-                self.callbacks.remove(&id);
-                "Websocket send message failed"
-            })
-            .await;
+        if stream.send_message(request_object).await.is_err() {
+            self.callbacks.remove(&id);
+            return Err("Pipe send message failed");
+        }
+        Ok(())
     }
 
     pub fn feed_response(
@@ -128,8 +249,21 @@ impl ClientWebsocket {
         response: serde_json::Value,
     ) -> Result<(), FeedResponseError> {
         if let Some(id) = id.as_u64() {
-            if let Some(callback) = self.callbacks.remove(&id) {
-                let _ = callback.send(response);
+            if let Some(pending) = self.callbacks.remove(&id) {
+                let metrics = &self.app_config.metrics;
+                metrics.client_call(&pending.call, pending.sent_at.elapsed());
+                let failed =
+                    matches!(&response, serde_json::Value::Object(m) if m.contains_key("error"));
+                match pending.watch {
+                    Some(WatchChange::Open(id)) if !failed && self.watchers.insert(id.clone()) => {
+                        metrics.watchers_changed(1)
+                    }
+                    Some(WatchChange::Close(id)) if self.watchers.remove(&id) => {
+                        metrics.watchers_changed(-1)
+                    }
+                    _ => {}
+                }
+                let _ = pending.callback.send(response);
                 return Ok(());
             } else {
                 return Err(FeedResponseError::NoRegisteredCallback(id));
@@ -147,6 +281,37 @@ impl ClientWebsocket {
     }
 }
 
+/// Name of the call (`fs.rm`, or `download` of `{"internal": [id, {"download": ...}]}`)
+/// and the watcher it opens or closes.
+fn describe_call(request: &serde_json::Value) -> (String, Option<WatchChange>) {
+    let (key, value) = match request.as_object().and_then(|m| m.iter().next()) {
+        Some(entry) => entry,
+        None => return (String::new(), None),
+    };
+    match (key.as_str(), value) {
+        ("internal", serde_json::Value::Array(argument)) => {
+            let call = argument
+                .get(1)
+                .and_then(|api_call| api_call.as_object())
+                .and_then(|api_call| api_call.keys().next())
+                .cloned()
+                .unwrap_or_default();
+            (call, None)
+        }
+        ("watch", serde_json::Value::String(id)) => {
+            (key.clone(), Some(WatchChange::Open(id.clone())))
+        }
+        ("watch", serde_json::Value::Object(request)) if request.contains_key("close") => {
+            let watch = request
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map(|id| WatchChange::Close(id.to_string()));
+            (key.clone(), watch)
+        }
+        _ => (key.clone(), None),
+    }
+}
+
 #[derive(Debug)]
 pub enum FeedResponseError {
     UnknownId(serde_json::Value),
@@ -158,13 +323,40 @@ pub enum SendEventError {
     EventChannelClosed,
 }
 
-pub struct Client {}
+pub struct Client {
+    app_config: Arc<AppConfig>,
+    host: String,
+    port: String,
+}
+
+impl Client {
+    pub fn new(app_config: Arc<AppConfig>, host: String, port: String) -> Self {
+        Self {
+            app_config,
+            host,
+            port,
+        }
+    }
+}
 
 #[async_trait]
 impl russh::client::Handler for Client {
     type Error = russh::Error;
 
-    async fn check_server_key(self, _: &key::PublicKey) -> Result<(Self, bool), Self::Error> {
-        Ok((self, true))
+    async fn check_server_key(
+        self,
+        server_public_key: &key::PublicKey,
+    ) -> Result<(Self, bool), Self::Error> {
+        let known_hosts = &self.app_config.known_hosts;
+        match known_hosts.verify(&self.host, &self.port, server_public_key) {
+            Ok(_) => Ok((self, true)),
+            Err(err) => {
+                self.app_config.logger.err(format!(
+                    "Refuse ssh server ({}:{}): {}",
+                    self.host, self.port, err
+                ));
+                Ok((self, false))
+            }
+        }
     }
 }
@@ -1,7 +1,8 @@
 use crate::common::{
     app_config::AppConfig,
+    audit::Transfer,
     forward_async_read_to_sender,
-    websocket_peer::{ClientHttp, ClientWebsocket},
+    websocket_peer::{ClientConnection, ClientHttp},
     ResponseType,
 };
 use crate::ResponseUnit;
@@ -20,8 +21,17 @@ pub fn file_to_stream(file: tokio::fs::File) -> mpsc::Receiver<ResponseUnit> {
     return rx;
 }
 
-pub async fn forward_body_to_sender(mut body: Incoming, mut sender: mpsc::Sender<ResponseUnit>) {
+/// Return bytes of data frames forwarded.
+pub async fn forward_body_to_sender(
+    mut body: Incoming,
+    mut sender: mpsc::Sender<ResponseUnit>,
+) -> u64 {
+    let mut forwarded = 0;
     while let Some(f) = body.frame().await {
+        let len = match &f {
+            Ok(frame) => frame.data_ref().map(|data| data.len() as u64).unwrap_or(0),
+            Err(_) => 0,
+        };
         let f = match f {
             Ok(f) => Ok(f),
             Err(e) => {
@@ -32,7 +42,9 @@ pub async fn forward_body_to_sender(mut body: Incoming, mut sender: mpsc::Sender
         if let Err(_) = sender.send(f).await {
             break; // error cause by receiver dropped, no need to send more data to sender
         };
+        forwarded += len;
     }
+    forwarded
 }
 
 pub const BUF_SIZE: usize = 8;
@@ -41,8 +53,9 @@ pub async fn request_internal_client_http_connection(
     app_config: &Arc<AppConfig>,
     req: Request<Incoming>,
     queue: Arc<Mutex<ClientHttp>>,
-    conn: Arc<Mutex<ClientWebsocket>>,
+    conn: Arc<Mutex<ClientConnection>>,
     api_call: serde_json::Value,
+    transfer: Transfer,
 ) -> Result<ResponseType, InternalClientHttpConnectionError> {
     let (tx, rx) = oneshot::channel();
     let id = {
@@ -62,6 +75,7 @@ pub async fn request_internal_client_http_connection(
                     let mut queue = queue.lock().await;
                     queue.cancel_register(&id);
                 }
+                transfer.fail("lost internal client connection");
                 return Err(InternalClientHttpConnectionError::LostInternalClientConnection);
             }
         }
@@ -73,27 +87,40 @@ pub async fn request_internal_client_http_connection(
                     queue.cancel_register(&id);
                 }
                 app_config.logger.err(format!("upload error {:?}", e));
+                transfer.fail(match e {
+                    Ok(serde_json::Value::Object(m)) if m.contains_key("error") => {
+                        m["error"].clone()
+                    }
+                    _ => json!("internal client rejected"),
+                });
                 return Err(InternalClientHttpConnectionError::InternalClientRejectConnection);
             }
         }
     }
     let internal_client_req = rx.await;
     match internal_client_req {
-        Ok((internal_req, internal_tx)) => {
+        Ok((internal_headers, internal_stream)) => {
             let (req_parts, req_body) = req.into_parts();
-            let (internal_parts, internal_body) = internal_req.into_parts();
             let (tx, rx) = mpsc::channel(BUF_SIZE);
             let body = StreamBody::new(rx);
             let mut response = Response::new(body);
-            *response.extensions_mut() = internal_parts.extensions;
-            *response.version_mut() = internal_parts.version.to_owned();
-            *response.headers_mut() = internal_parts.headers.to_owned();
+            *response.headers_mut() = internal_headers;
             *response.version_mut() = req_parts.version;
-            tokio::spawn(forward_body_to_sender(internal_body, tx));
-            tokio::spawn(forward_body_to_sender(req_body, internal_tx));
+            let (internal_tx, internal_rx) = mpsc::channel(BUF_SIZE);
+            let sent = tokio::spawn(internal_stream.source.forward(tx));
+            let received = tokio::spawn(forward_body_to_sender(req_body, internal_tx));
+            tokio::spawn(internal_stream.sink.forward(internal_rx));
+            let app_config = app_config.clone();
+            tokio::spawn(async move {
+                let (received, sent) = futures::join!(received, sent);
+                let kind = transfer.kind();
+                let bytes = transfer.finish(received.unwrap_or(0), sent.unwrap_or(0));
+                app_config.metrics.transferred(kind, bytes);
+            });
             return Ok(response);
         }
         _ => {
+            transfer.fail("internal client didn't open the stream");
             return Err(InternalClientHttpConnectionError::Unknown);
         }
     }
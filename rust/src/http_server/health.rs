@@ -0,0 +1,98 @@
+use bytes::Bytes;
+use futures::{channel::mpsc::channel, SinkExt};
+use http_body_util::StreamBody;
+use hyper::{body::Frame, header, http::HeaderValue, Method, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use crate::common::{
+    app_config::AppConfig, health::Check, websocket_peer::Client, AppContext, ResponseType,
+};
+
+const SSH_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `/healthz` (liveness): listeners accept connections and the certificate is valid.
+/// `/readyz` (readiness): in addition, local sshd is reachable and presents a known host key.
+/// Answer 200 if every check passes or 503 otherwise, with the checks in JSON.
+pub async fn on_health(
+    context: &AppContext,
+    method: &Method,
+    ready: bool,
+) -> Result<ResponseType, Infallible> {
+    let app_config = &context.app_config;
+    let health = &app_config.health;
+    let mut checks = vec![
+        (
+            "listener",
+            health.listener(app_config.listen_addresses.len()),
+        ),
+        (
+            "certificate",
+            health.certificate(app_config.no_tls, app_config.certificate_expiry_margin),
+        ),
+    ];
+    if ready {
+        checks.push(("ssh", health.ssh(probe_ssh(app_config)).await));
+    }
+    let ok = checks.iter().all(|(_, check)| check.ok);
+    let checks = checks
+        .into_iter()
+        .map(|(name, check)| (name.to_string(), json!(check)))
+        .collect::<Map<String, Value>>();
+    let body = json!({
+        "status": match ok {
+            true => "ok",
+            false => "fail",
+        },
+        "checks": checks,
+    });
+
+    let (mut tx, rx) = channel(1);
+    // h2 refuses body of HEAD response
+    if method != Method::HEAD {
+        let _ = tx
+            .send(Ok(Frame::data(Bytes::from(body.to_string()))))
+            .await;
+    }
+    let mut response = Response::new(StreamBody::new(rx));
+    if !ok {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(response)
+}
+
+/// Key exchange with local sshd the way sign in does, the host key is verified by known hosts.
+async fn probe_ssh(app_config: &Arc<AppConfig>) -> Check {
+    let host = "localhost".to_string();
+    let port = app_config.local_ssh_port.clone();
+    let address = format!("{}:{}", host, port);
+    let config = Arc::new(russh::client::Config::default());
+    let sh = Client::new(app_config.clone(), host, port);
+    let connect = russh::client::connect(config, address.as_str(), sh);
+    match tokio::time::timeout(SSH_PROBE_TIMEOUT, connect).await {
+        Ok(Ok(session)) => {
+            let _ = session
+                .disconnect(russh::Disconnect::ByApplication, "", "English")
+                .await;
+            Check::pass(format!(
+                "ssh server ({}) presents a known host key",
+                address
+            ))
+        }
+        Ok(Err(russh::Error::UnknownKey)) => Check::fail(format!(
+            "host key verification failed for {}, the ssh server may be impersonated",
+            address
+        )),
+        Ok(Err(e)) => Check::fail(format!("ssh server ({}) unreachable: {}", address, e)),
+        Err(_) => Check::fail(format!(
+            "ssh server ({}) didn't finish key exchange in {:?}",
+            address, SSH_PROBE_TIMEOUT
+        )),
+    }
+}
@@ -0,0 +1,41 @@
+use bytes::Bytes;
+use futures::{channel::mpsc::channel, SinkExt};
+use http_body_util::StreamBody;
+use hyper::{body::Frame, header, http::HeaderValue, Method, Request, Response};
+use std::convert::Infallible;
+
+use super::health::on_health;
+use super::not_found::not_found;
+use crate::common::{AppContext, ResponseType};
+
+/// Prometheus text exposition at `/metrics` of the metrics listener,
+/// which also answers `/healthz` and `/readyz` for probes on the private network.
+pub async fn on_metrics(
+    context: &AppContext,
+    req: Request<hyper::body::Incoming>,
+) -> Result<ResponseType, Infallible> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET | &Method::HEAD, "/healthz") => {
+            return on_health(context, req.method(), false).await
+        }
+        (&Method::GET | &Method::HEAD, "/readyz") => {
+            return on_health(context, req.method(), true).await
+        }
+        (_, "/metrics") => {}
+        (_, path) => {
+            let message = format!("Unknown metrics request {:?}", path);
+            return Ok(not_found(&context.app_config, message).await);
+        }
+    }
+    let sessions = context.websocket_peers.lock().await.len();
+    let text = context.app_config.metrics.render(sessions);
+
+    let (mut tx, rx) = channel(1);
+    let _ = tx.send(Ok(Frame::data(Bytes::from(text)))).await;
+    let mut response = Response::new(StreamBody::new(rx));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+    Ok(response)
+}
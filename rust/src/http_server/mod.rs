@@ -1,8 +1,7 @@
-use crate::common::{
-    AppContext, ResponseType,
-};
-use hyper::{Method, Request};
-use std::{convert::Infallible, net::SocketAddr, path::PathBuf};
+use crate::common::{app_config::AppConfig, logger::Level, AppContext, ResponseType};
+use hyper::{header, Method, Request};
+use serde_json::json;
+use std::{convert::Infallible, net::SocketAddr, path::PathBuf, time::Instant};
 
 mod components;
 
@@ -12,9 +11,6 @@ use not_found::not_found;
 mod file_send;
 use file_send::file_send;
 
-mod client;
-use client::on_client;
-
 mod download;
 use download::on_download;
 
@@ -24,7 +20,71 @@ use upload::on_upload;
 mod preview;
 use preview::on_preview;
 
+mod session;
+use session::{on_session, session_token};
+
+mod metrics;
+pub use metrics::on_metrics;
+
+mod health;
+use health::on_health;
+
+mod redirect;
+use redirect::redirect_to_base_path;
+pub use redirect::redirect_to_https;
+
+/// Loopback or trusted proxy (where load balancer probes come from).
+fn is_probe_peer(app_config: &AppConfig, addr: &SocketAddr) -> bool {
+    let ip = addr.ip().to_canonical();
+    ip.is_loopback()
+        || app_config
+            .trusted_proxies
+            .iter()
+            .any(|cidr| cidr.contains(&ip))
+}
+
+/// `https` tells whether browser visits over https, directly or through a trusted proxy.
 pub async fn on_http(
+    context: &AppContext,
+    addr: &SocketAddr,
+    https: bool,
+    req: Request<hyper::body::Incoming>,
+) -> Result<ResponseType, Infallible> {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let mut res = route(context, addr, req).await?;
+    // probes of load balancer come every few seconds
+    let level = match context.app_config.route_path(&path) {
+        Some("/healthz" | "/readyz") => Level::Debug,
+        _ => Level::Info,
+    };
+    // body of downloads keeps streaming after this
+    context
+        .app_config
+        .logger
+        .event(level, "Http request")
+        .field("peer", addr)
+        .field("method", method)
+        .field("route", path)
+        .value("status", res.status().as_u16())
+        .duration("duration", start.elapsed())
+        .emit();
+    // route may set its own one
+    let headers = res.headers_mut();
+    for (name, value) in context.app_config.security_headers.iter() {
+        // browser ignores it over plain http, but a wrong one pins a host that has no https
+        if !https && name == header::STRICT_TRANSPORT_SECURITY {
+            continue;
+        }
+        if !headers.contains_key(name) {
+            headers.insert(name, value.clone());
+        }
+    }
+    Ok(res)
+}
+
+async fn route(
     context: &AppContext,
     addr: &SocketAddr,
     req: Request<hyper::body::Incoming>,
@@ -32,65 +92,116 @@ pub async fn on_http(
     let app_config = &context.app_config;
     let peer_map = &context.websocket_peers;
 
-    app_config.logger.info(format!(
-        "New http ({}) connection: {}",
-        req.uri().path(),
-        addr
-    ));
+    let path = match app_config.route_path(req.uri().path()) {
+        // relative urls of page only work with trailing slash
+        Some("") => return redirect_to_base_path(app_config).await,
+        Some(path) => path.to_string(),
+        None => {
+            let message = format!("Request out of base path {:?}", req.uri().path());
+            return Ok(not_found(app_config, message).await);
+        }
+    };
     use url::form_urlencoded::parse;
-    if let Some(query) = req.uri().query() {
-        let mut peers = parse(query.as_bytes()).into_owned();
-        if let Some((_, token)) = peers.find(|(key, _)| key.as_str() == "t") {
-            let peer_map = peer_map.lock().await;
-            let peer = peer_map.get(&token);
-            match peer {
-                Some(peer) => {
-                    let queue = peer.client_http.clone();
-                    let conn = peer.client_websocket.clone();
-                    drop(peer_map);
-                    let mut upload_dir = vec![];
-                    let mut upload_filename = None;
-                    let mut preview = None;
-                    let mut files = vec![];
-                    for (key, value) in peers.into_iter() {
-                        match key.as_str() {
-                            "p" => {
-                                files.push(value);
-                            }
-                            "u" => {
-                                upload_dir.push(value);
-                            }
-                            "n" => {
-                                upload_filename = Some(value);
-                            }
-                            "v" => {
-                                preview = Some(value);
-                            }
-                            _ => {}
+    let peers: Vec<(String, String)> = match req.uri().query() {
+        Some(query) => parse(query.as_bytes()).into_owned().collect(),
+        None => vec![],
+    };
+    let token = match path.as_str() {
+        "/download" | "/upload" | "/preview" => session_token(&req),
+        _ => None,
+    };
+    let token = match (token, peers.iter().find(|(key, _)| key.as_str() == "t")) {
+        (Some(token), _) => Some(token),
+        (None, Some((_, token))) => {
+            if !app_config.query_token {
+                return Ok(not_found(
+                    app_config,
+                    "Session token in query string is disabled (use session cookie or authorization header instead)",
+                )
+                .await);
+            }
+            app_config.logger.warn(format!(
+                "Deprecated session token in query string from {}",
+                addr
+            ));
+            Some(token.clone())
+        }
+        (None, None) => None,
+    };
+    if let Some(token) = token {
+        let peer_map = peer_map.lock().await;
+        let peer = peer_map.get(&token);
+        match peer {
+            Some(peer) => {
+                let queue = peer.client_http.clone();
+                let conn = peer.client_connection.clone();
+                let clock = peer.clock.clone();
+                let auditor = peer.auditor.clone();
+                drop(peer_map);
+                if let Err(reason) = clock.deadline().await {
+                    let message = format!("Session expired ({})", reason);
+                    return Ok(not_found(app_config, message).await);
+                }
+                clock.touch().await;
+                let mut upload_dir = vec![];
+                let mut upload_filename = None;
+                let mut preview = None;
+                let mut files = vec![];
+                for (key, value) in peers.into_iter() {
+                    match key.as_str() {
+                        "p" => {
+                            files.push(value);
                         }
-                    }
-                    if files.len() != 0 {
-                        return on_download(app_config, req, queue, conn, files).await;
-                    } else if upload_dir.len() != 0 {
-                        let dir: PathBuf = upload_dir.iter().collect();
-                        if let Some(dir) = dir.as_os_str().to_str() {
-                            return on_upload(app_config, req, queue, conn, dir, upload_filename)
-                                .await;
+                        "u" => {
+                            upload_dir.push(value);
+                        }
+                        "n" => {
+                            upload_filename = Some(value);
+                        }
+                        "v" => {
+                            preview = Some(value);
                         }
-                    } else if let Some(preview) = preview {
-                        return on_preview(app_config, req, queue, conn, preview).await;
+                        _ => {}
                     }
-                    return Ok(not_found(app_config, "Unknown request").await);
                 }
-                None => {
-                    return Ok(not_found(app_config, "Auth request failed").await);
+                if !files.is_empty() {
+                    let transfer = auditor.transfer(addr, "download", json!({ "paths": files }));
+                    return on_download(app_config, req, queue, conn, transfer, files).await;
+                } else if !upload_dir.is_empty() {
+                    let dir: PathBuf = upload_dir.iter().collect();
+                    if let Some(dir) = dir.as_os_str().to_str() {
+                        let fields = json!({ "path": dir, "filename": upload_filename });
+                        let transfer = auditor.transfer(addr, "upload", fields);
+                        return on_upload(
+                            app_config,
+                            req,
+                            queue,
+                            conn,
+                            transfer,
+                            dir,
+                            upload_filename,
+                        )
+                        .await;
+                    }
+                } else if let Some(preview) = preview {
+                    let transfer = auditor.transfer(addr, "preview", json!({ "path": preview }));
+                    return on_preview(app_config, req, queue, conn, transfer, preview).await;
                 }
+                return Ok(not_found(app_config, "Unknown request").await);
+            }
+            None => {
+                return Ok(not_found(app_config, "Auth request failed").await);
             }
         }
     }
 
-    match (req.method(), req.uri().path()) {
-        (_, "/client") => on_client(app_config, peer_map, addr, req).await,
+    match (req.method(), path.as_str()) {
+        (&Method::POST, "/session") => on_session(app_config, peer_map, req).await,
+        (&Method::GET | &Method::HEAD, "/healthz") => on_health(context, req.method(), false).await,
+        // readiness connects to sshd, not for anyone on the internet to trigger
+        (&Method::GET | &Method::HEAD, "/readyz") if is_probe_peer(app_config, addr) => {
+            on_health(context, req.method(), true).await
+        }
         (&Method::GET | &Method::HEAD, "" | "/") => file_send(app_config, &req, "index.html").await,
         (&Method::GET | &Method::HEAD, path) => file_send(app_config, &req, &path[1..]).await,
         (m, path) => Ok(not_found(app_config, format!("Unknown request {:?} {:?}", m, path)).await),
@@ -0,0 +1,55 @@
+use futures::channel::mpsc::channel;
+use http_body_util::StreamBody;
+use hyper::{header, http::HeaderValue, Request, Response, StatusCode};
+use std::convert::Infallible;
+
+use crate::common::{app_config::AppConfig, ResponseType};
+
+/// Permanent redirect (keeps method and body) to the same path on https origin.
+pub async fn redirect_to_https(
+    app_config: &AppConfig,
+    req: Request<hyper::body::Incoming>,
+) -> Result<ResponseType, Infallible> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| url::Url::parse(&format!("http://{}", host)).ok())
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .or_else(|| app_config.listen_hosts.first().cloned())
+        .unwrap_or_else(|| "localhost".to_string());
+    // the proxy in front serves https on the default port
+    let port = match app_config.tcp_port() {
+        Some(443) | None => None,
+        _ if app_config.no_tls => None,
+        port => port,
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let location = match port {
+        Some(port) => format!("https://{}:{}{}", host, port, path),
+        None => format!("https://{}{}", host, path),
+    };
+
+    let (_, rx) = channel(0);
+    let mut response = Response::new(StreamBody::new(rx));
+    *response.status_mut() = StatusCode::PERMANENT_REDIRECT;
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    Ok(response)
+}
+
+/// Redirect base path without trailing slash to the page.
+pub async fn redirect_to_base_path(app_config: &AppConfig) -> Result<ResponseType, Infallible> {
+    let (_, rx) = channel(0);
+    let mut response = Response::new(StreamBody::new(rx));
+    *response.status_mut() = StatusCode::PERMANENT_REDIRECT;
+    if let Ok(location) = HeaderValue::from_str(&format!("{}/", app_config.base_path)) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    Ok(response)
+}
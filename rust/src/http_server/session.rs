@@ -0,0 +1,92 @@
+use super::not_found::not_found;
+use crate::common::logger::session_id;
+use crate::common::{app_config::AppConfig, websocket_peer::WebSocketPeer, ResponseType};
+use bytes::Bytes;
+use futures::lock::Mutex;
+use futures::{channel::mpsc::channel, SinkExt};
+use http_body_util::StreamBody;
+use hyper::body::{Frame, Incoming};
+use hyper::header::{self, HeaderValue};
+use hyper::{Request, Response};
+use serde_json::json;
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+/// Each session has its own cookie `web-ssh-tool-session-<session id>`,
+/// so that tabs signed in to different sessions don't overwrite each other's.
+pub const SESSION_COOKIE: &str = "web-ssh-tool-session";
+
+fn cookie_name(session: &str) -> String {
+    format!("{}-{}", SESSION_COOKIE, session)
+}
+
+/// Session token from `Authorization: Bearer <token>` header,
+/// or the cookie of the session that query `s=<session id>` names.
+pub fn session_token(req: &Request<Incoming>) -> Option<String> {
+    let headers = req.headers();
+    if let Some(Ok(authorization)) = headers.get(header::AUTHORIZATION).map(|h| h.to_str()) {
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            return Some(token.trim().to_string());
+        }
+    }
+    use url::form_urlencoded::parse;
+    let session = parse(req.uri().query()?.as_bytes())
+        .find(|(key, _)| key == "s")
+        .map(|(_, value)| value)?;
+    let name = cookie_name(&session);
+    for cookie in headers.get_all(header::COOKIE) {
+        if let Ok(cookie) = cookie.to_str() {
+            for pair in cookie.split(';') {
+                if let Some((key, value)) = pair.trim().split_once('=') {
+                    if key == name {
+                        return Some(value.to_string());
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Exchange the token (from sign in websocket) in authorization header for a session cookie
+/// that browser sends with download link and preview page by itself.
+/// Answer the session id that the links carry in query `s` to pick the cookie.
+pub async fn on_session(
+    app_config: &Arc<AppConfig>,
+    peer_map: &Arc<Mutex<HashMap<String, WebSocketPeer>>>,
+    req: Request<Incoming>,
+) -> Result<ResponseType, Infallible> {
+    let token = match session_token(&req) {
+        Some(token) => token,
+        None => return Ok(not_found(app_config, "Session request without token").await),
+    };
+    let clock = match peer_map.lock().await.get(&token) {
+        Some(peer) => peer.clock.clone(),
+        None => return Ok(not_found(app_config, "Auth request failed").await),
+    };
+    if let Err(reason) = clock.deadline().await {
+        return Ok(not_found(app_config, format!("Session expired ({})", reason)).await);
+    }
+    let session = session_id(&token);
+    let cookie = format!(
+        "{}={}; HttpOnly; Secure; SameSite=Strict; Path={}/",
+        cookie_name(&session),
+        token,
+        app_config.base_path
+    );
+    let (mut tx, rx) = channel(1);
+    let body = json!({ "session": session });
+    let _ = tx
+        .send(Ok(Frame::data(Bytes::from(body.to_string()))))
+        .await;
+    let mut response = Response::new(StreamBody::new(rx));
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        headers.append(header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
@@ -2,7 +2,8 @@ use super::components::request_internal_client_http_connection;
 use super::not_found::not_found;
 use crate::common::{
     app_config::AppConfig,
-    websocket_peer::{ClientHttp, ClientWebsocket},
+    audit::Transfer,
+    websocket_peer::{ClientConnection, ClientHttp},
     ResponseType,
 };
 use futures::lock::Mutex;
@@ -14,7 +15,8 @@ pub async fn on_upload(
     app_config: &Arc<AppConfig>,
     req: Request<Incoming>,
     queue: Arc<Mutex<ClientHttp>>,
-    conn: Arc<Mutex<ClientWebsocket>>,
+    conn: Arc<Mutex<ClientConnection>>,
+    transfer: Transfer,
     dir: &str,
     filename: Option<String>,
 ) -> Result<ResponseType, Infallible> {
@@ -33,6 +35,7 @@ pub async fn on_upload(
         queue,
         conn,
         json!({"upload": m}),
+        transfer,
     )
     .await
     {
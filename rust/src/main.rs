@@ -4,12 +4,17 @@ mod tls;
 mod websocket_client;
 mod websocket_server;
 use common::app_config::AppConfig;
+use common::forwarded::{client_address, is_forwarded_https};
+use common::listener::{sniff_h2c, ListenAddress, Listener};
+use common::logger::Level;
+use common::login_limiter::LoginLimiter;
+use common::shutdown::Shutdown;
 use common::{AppContext, ResponseType, ResponseUnit};
 use futures::channel::mpsc;
 use futures::lock::Mutex;
 use futures::{Future, SinkExt, StreamExt};
 use http_body_util::StreamBody;
-use http_server::on_http;
+use http_server::{on_http, on_metrics, redirect_to_https};
 use hyper::header;
 use hyper::header::HeaderValue;
 use hyper::rt::Executor;
@@ -19,9 +24,15 @@ use hyper::{Method, Request, Response, StatusCode, Version};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tls::{load_certs, load_keys};
+use std::time::Duration;
+use tls::{
+    certificate_common_name, certificate_fingerprint, load_certs, load_client_verifier, load_keys,
+    load_or_generate_self_signed, SelfSigned, SniResolver, HAS_INTERNAL_CERTIFICATE,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::server::ClientCertVerifier;
 use tokio_rustls::rustls::ServerConfig;
 use tokio_rustls::TlsAcceptor;
 use tokio_tungstenite::tungstenite::{
@@ -33,126 +44,199 @@ use tokio_tungstenite::WebSocketStream;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let app_config = Arc::new(AppConfig::new());
-    if let Some(token) = &app_config.client {
-        // @TODO: send log to master
-        // internal client mode
-        use tls::CustomServerCertVerifier;
-        use tokio_rustls::rustls::ClientConfig;
-        use tokio_tungstenite::{connect_async_tls_with_config, Connector};
-        let connector = Connector::Rustls(Arc::new(
-            ClientConfig::builder()
-                .with_safe_defaults()
-                .with_custom_certificate_verifier(Arc::new(CustomServerCertVerifier)) // @TODO: verify server cert
-                .with_no_client_auth(),
-        ));
-
-        let master_server = format!(
-            "wss://localhost:{}/client?t={}",
-            app_config.listen_address.port(),
-            token
-        );
-        // use domain is more robust than SocketAddr
-        // 'localhost' will be convert to '::1' with 'to_socket_addrs'
-        // but '::1' can't connect to '127.0.0.1' and 'localhost' can connect to '127.0.0.1' with tokio_tungstenite
-        app_config.logger.info(format!(
-            "Running client mode (warning: this mode not for public usage) and connect to {}",
-            master_server
-        ));
-        match connect_async_tls_with_config(master_server, None, false, Some(connector)).await {
-            Ok((client, _)) => {
-                let _ = websocket_client::handle_request(&app_config, token, client).await;
-            }
-            Err(e) => {
-                app_config
-                    .logger
-                    .err(format!("Client mode failed to connect to master! {:?}", e));
-            }
+    if app_config.client {
+        // internal client mode, master talks to it over stdin and stdout of the ssh exec channel
+        if let Err(e) = websocket_client::handle_request(&app_config).await {
+            app_config
+                .logger
+                .err(format!("Client mode failed to serve master! {:?}", e));
+            app_config.logger.flush();
+            std::process::exit(1);
         }
+        app_config.logger.flush();
         std::process::exit(0);
-        // return Ok(());
     }
 
-    let (listener, cert_source, key_source) = futures::join!(
-        tokio::net::TcpListener::bind(&app_config.listen_address),
-        read_file(&app_config.certificate, "Failed to read certificate file"),
-        read_file(&app_config.private_key, "Failed to read private key file"),
-    );
-    let listener = listener?;
-
-    let certs = load_certs(&cert_source)?;
-    let mut keys = load_keys(&key_source)?;
-    let mut config = ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(certs, keys.pop().expect("SSL private key not found"))?;
-    config.alpn_protocols = [b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()].into();
-    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listeners = bind_listeners(&app_config)?;
+    let redirect_listener = match &app_config.redirect_http {
+        Some(address) => Some(tokio::net::TcpListener::bind(address).await?),
+        None => None,
+    };
+    let metrics_listener = match &app_config.metrics_listen {
+        Some(address) => Some(tokio::net::TcpListener::bind(address).await?),
+        None => None,
+    };
+    // swapped on SIGHUP, connections keep the acceptor they were accepted with
+    let acceptor = match app_config.no_tls {
+        true => None,
+        false => Some(Arc::new(Mutex::new(load_acceptor(&app_config).await?))),
+    };
 
     // Create the event loop and TCP listener we'll accept connections on.
     println!("{}", app_config);
-    println!(
-        "Please visit: https://localhost:{}\n",
-        app_config.listen_address.port()
-    );
+    if let Some(port) = app_config.tcp_port() {
+        println!(
+            "Please visit: {}://localhost:{}\n",
+            match app_config.no_tls {
+                true => "http",
+                false => "https",
+            },
+            port
+        );
+    }
 
-    let (mut tx, rx) = mpsc::channel(0);
-    tokio::spawn(async move { while let Ok(_) = tx.send(listener.accept().await).await {} });
+    let shutdown = Shutdown::default();
+    tokio::spawn(handle_signals(
+        app_config.clone(),
+        shutdown.clone(),
+        acceptor.clone(),
+    ));
+
+    if let Some(listener) = redirect_listener {
+        let redirect_config = app_config.clone();
+        let handler = move |req| {
+            let app_config = redirect_config.clone();
+            async move { redirect_to_https(&app_config, req).await }
+        };
+        tokio::spawn(serve_plain_http(
+            app_config.clone(),
+            shutdown.clone(),
+            listener,
+            handler,
+        ));
+    }
+
+    let (tx, rx) = mpsc::channel(0);
+    for listener in listeners {
+        let mut tx = tx.clone();
+        let draining = shutdown.draining.clone();
+        let listener_config = app_config.clone();
+        tokio::spawn(async move {
+            listener_config.health.listener_started();
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = draining.cancelled() => break, // stop accepting
+                };
+                if tx.send(accepted).await.is_err() {
+                    break;
+                }
+            }
+            listener_config.health.listener_stopped();
+        });
+    }
+    drop(tx);
 
     let websocket_peers = Arc::new(Mutex::new(HashMap::new()));
     let authenticate_queues = Arc::new(Mutex::new(HashMap::new()));
-    let suspended_clients = Arc::new(Mutex::new(HashMap::new()));
+    let login_limiter = Arc::new(Mutex::new(LoginLimiter::new(
+        app_config.login_window,
+        app_config.login_max_failures,
+        app_config.login_backoff,
+        app_config.login_ban,
+    )));
+    let parked_sessions = Arc::new(Mutex::new(HashMap::new()));
     let context = AppContext {
         app_config: app_config.clone(),
         websocket_peers,
         authenticate_queues,
-        suspended_clients,
+        login_limiter,
+        parked_sessions,
+        shutdown: shutdown.clone(),
     };
+    if let Some(listener) = metrics_listener {
+        let metrics_context = context.clone();
+        let handler = move |req| {
+            let context = metrics_context.clone();
+            async move { on_metrics(&context, req).await }
+        };
+        tokio::spawn(serve_plain_http(
+            app_config.clone(),
+            shutdown.clone(),
+            listener,
+            handler,
+        ));
+    }
     let http1_service = http1::Builder::new();
     let http2_service = http2::Builder::new(TokioExecutor);
 
     // @TODO: concurrent limit can be set by arguments
-    rx.for_each_concurrent(None, |item| async {
+    let serve = rx.for_each_concurrent(None, |item| async {
         match item {
             Ok((stream, addr)) => {
+                // connection from trusted proxy is filtered by the client address of each request
+                let from_proxy = is_trusted_proxy(&app_config, &addr);
+                if !from_proxy && !app_config.ip_filter.is_allowed(&addr.ip()) {
+                    app_config
+                        .logger
+                        .warn(format!("Refuse connection from {:?} (ip filter)", addr));
+                    return;
+                }
+                let acceptor = match &acceptor {
+                    Some(acceptor) => acceptor.lock().await.clone(),
+                    None => {
+                        let (is_h2, stream) = match sniff_h2c(stream).await {
+                            Some(sniffed) => sniffed,
+                            None => {
+                                app_config
+                                    .logger
+                                    .event(Level::Debug, "Close silent connection")
+                                    .field("peer", addr)
+                                    .emit();
+                                return;
+                            }
+                        };
+                        let res = serve_connection(
+                            &context,
+                            &http1_service,
+                            &http2_service,
+                            stream,
+                            addr,
+                            None,
+                            is_h2,
+                        )
+                        .await;
+                        if let Err(e) = res {
+                            app_config
+                                .logger
+                                .err(format!("Failed to serve connection: {:?}", e));
+                        }
+                        return;
+                    }
+                };
                 match acceptor.accept(stream).await {
                     Ok(stream) => {
                         let (_, session) = stream.get_ref();
+                        let client_cert = session
+                            .peer_certificates()
+                            .and_then(|certs| certs.first())
+                            .and_then(certificate_common_name);
                         let is_h2 = match session.alpn_protocol() {
                             Some(alpn) => alpn == b"h2",
                             None => false,
                         };
-                        let res = if is_h2 {
-                            let handle = |req| {
-                                let context = context.clone();
-                                let addr = addr.clone();
-                                async move { on_http(&context, &addr, req).await }
-                            };
-                            // Warning:
-                            // ```let handle = move |req| on_http(&context, &addr, req);```
-                            // doesn't work!
-                            // Because the reference (for example &content) only catch by the sync function ```move |req| {/* */}```
-                            // But the sync function return a future and this future will be scheduled by tokio async machine and the lifecycle detach from the sync function.
-                            // So the future may or may not live longer than the sync function.
-                            // The lifecycle-detach future still access the sync function data so that causes error.
-                            http2_service
-                                .serve_connection(stream, service_fn(handle))
-                                .await
-                        } else {
-                            let handle = |req| http_websocket_classify(&context, &addr, req);
-                            http1_service
-                                .serve_connection(stream, service_fn(handle))
-                                .with_upgrades()
-                                .await
-                        };
+                        let res = serve_connection(
+                            &context,
+                            &http1_service,
+                            &http2_service,
+                            stream,
+                            addr,
+                            client_cert,
+                            is_h2,
+                        )
+                        .await;
                         if let Err(e) = res {
                             app_config
                                 .logger
                                 .err(format!("Failed to serve connection: {:?}", e));
                         }
                     }
-                    Err(e) => app_config
-                        .logger
-                        .err(format!("Failed to ssl handshake from {:?}: {:?}", addr, e)),
+                    Err(e) => {
+                        app_config.metrics.tls_handshake_failed();
+                        app_config
+                            .logger
+                            .err(format!("Failed to ssl handshake from {:?}: {:?}", addr, e))
+                    }
                 }
             }
             Err(e) => app_config.logger.err(format!(
@@ -160,19 +244,371 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 e
             )),
         }
-    })
-    .await;
+    });
+
+    let deadline = async {
+        shutdown.draining.cancelled().await;
+        tokio::time::sleep(app_config.shutdown_timeout).await;
+    };
+    tokio::select! {
+        _ = serve => {},
+        _ = deadline => app_config.logger.err("Shutdown deadline passed, drop in flight transfers"),
+    }
+    // every connection finished or the deadline passed
+    shutdown.closing.cancel();
+    shutdown.sessions.close();
+    let _ = tokio::time::timeout(Duration::from_secs(5), shutdown.sessions.wait()).await;
+    app_config.logger.info("Shutdown completed");
+    app_config.logger.flush();
+    app_config.audit_log.flush();
 
     Ok(())
 }
 
-async fn read_file(path: &Option<String>, expect: &str) -> Option<Vec<u8>> {
-    match path {
+/// Serve http1 (with websocket upgrade) or h2 on the connection until it closes,
+/// or finish in flight requests then close once draining.
+async fn serve_connection<S>(
+    context: &AppContext,
+    http1_service: &http1::Builder,
+    http2_service: &http2::Builder<TokioExecutor>,
+    stream: S,
+    peer: SocketAddr,
+    client_cert: Option<String>,
+    is_h2: bool,
+) -> Result<(), hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let draining = context.shutdown.draining.clone();
+    if is_h2 {
+        let handle = |req: Request<hyper::body::Incoming>| {
+            let context = context.clone();
+            async move {
+                let https = is_https(&context.app_config, &peer, req.headers());
+                match forwarded_client(&context, &peer, req.headers()) {
+                    Some(addr) => on_http(&context, &addr, https, req).await,
+                    None => Ok(forbidden()),
+                }
+            }
+        };
+        // Warning:
+        // ```let handle = move |req| on_http(&context, &addr, req);```
+        // doesn't work!
+        // Because the reference (for example &content) only catch by the sync function ```move |req| {/* */}```
+        // But the sync function return a future and this future will be scheduled by tokio async machine and the lifecycle detach from the sync function.
+        // So the future may or may not live longer than the sync function.
+        // The lifecycle-detach future still access the sync function data so that causes error.
+        let conn = http2_service.serve_connection(stream, service_fn(handle));
+        let mut conn = std::pin::pin!(conn);
+        tokio::select! {
+            res = conn.as_mut() => res,
+            _ = draining.cancelled() => {
+                // finish in flight requests then close
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+        }
+    } else {
+        let client_cert = &client_cert;
+        let peer = &peer;
+        let handle = move |req: Request<hyper::body::Incoming>| async move {
+            let https = is_https(&context.app_config, peer, req.headers());
+            match forwarded_client(context, peer, req.headers()) {
+                Some(addr) => {
+                    http_websocket_classify(context, &addr, https, client_cert, req).await
+                }
+                None => Ok(forbidden()),
+            }
+        };
+        let conn = http1_service
+            .serve_connection(stream, service_fn(handle))
+            .with_upgrades();
+        let mut conn = std::pin::pin!(conn);
+        tokio::select! {
+            res = conn.as_mut() => res,
+            _ = draining.cancelled() => {
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+        }
+    }
+}
+
+/// Bind every listen address, ipv6 one is ipv6 only if ipv4 one of same port is also listened.
+fn bind_listeners(app_config: &AppConfig) -> Result<Vec<Listener>, Box<dyn Error>> {
+    let addresses = &app_config.listen_addresses;
+    let has_ipv4 = |port: u16| {
+        addresses.iter().any(|address| match address {
+            ListenAddress::Tcp(address) => address.is_ipv4() && address.port() == port,
+            ListenAddress::Unix(_) => false,
+        })
+    };
+    addresses
+        .iter()
+        .map(|address| {
+            let v6_only = match address {
+                ListenAddress::Tcp(address) => has_ipv4(address.port()),
+                ListenAddress::Unix(_) => false,
+            };
+            Listener::bind(address, v6_only)
+                .map_err(|e| format!("Failed to listen on {}: {}", address, e).into())
+        })
+        .collect()
+}
+
+/// Serve a side listener (http to https redirect or metrics) with plain http1 until shutdown.
+async fn serve_plain_http<F, R>(
+    app_config: Arc<AppConfig>,
+    shutdown: Shutdown,
+    listener: tokio::net::TcpListener,
+    handler: F,
+) where
+    F: Fn(Request<hyper::body::Incoming>) -> R + Clone + Send + 'static,
+    R: Future<Output = Result<ResponseType, Infallible>> + Send + 'static,
+{
+    let http1_service = http1::Builder::new();
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    app_config.logger.err(format!(
+                        "Failed to accept connection on {:?}: {:?}",
+                        listener.local_addr(),
+                        e
+                    ));
+                    continue;
+                }
+            },
+            _ = shutdown.draining.cancelled() => break,
+        };
+        let draining = shutdown.draining.clone();
+        let conn = http1_service.serve_connection(stream, service_fn(handler.clone()));
+        tokio::spawn(async move {
+            let mut conn = std::pin::pin!(conn);
+            tokio::select! {
+                _ = conn.as_mut() => {},
+                _ = draining.cancelled() => {
+                    conn.as_mut().graceful_shutdown();
+                    let _ = conn.await;
+                }
+            }
+        });
+    }
+}
+
+fn is_trusted_proxy(app_config: &AppConfig, addr: &SocketAddr) -> bool {
+    app_config
+        .trusted_proxies
+        .iter()
+        .any(|cidr| cidr.contains(&addr.ip()))
+}
+
+/// Whether browser visits over https, either served here or through a trusted proxy that tells so.
+fn is_https(app_config: &AppConfig, peer: &SocketAddr, headers: &header::HeaderMap) -> bool {
+    !app_config.no_tls || (is_trusted_proxy(app_config, peer) && is_forwarded_https(headers))
+}
+
+/// Client address of request, the one forwarded by trusted proxy is checked by ip filter here
+/// because the connection from proxy skipped it. None if refused.
+fn forwarded_client(
+    context: &AppContext,
+    peer: &SocketAddr,
+    headers: &header::HeaderMap,
+) -> Option<SocketAddr> {
+    let app_config = &context.app_config;
+    if !is_trusted_proxy(app_config, peer) {
+        return Some(*peer);
+    }
+    let addr = client_address(&app_config.trusted_proxies, peer, headers);
+    if !app_config.ip_filter.is_allowed(&addr.ip()) {
+        app_config.logger.warn(format!(
+            "Refuse request from {:?} through proxy {:?} (ip filter)",
+            addr, peer
+        ));
+        return None;
+    }
+    Some(addr)
+}
+
+fn forbidden() -> ResponseType {
+    let (_, rx) = mpsc::channel(0);
+    let mut res = Response::new(StreamBody::new(rx));
+    *res.status_mut() = StatusCode::FORBIDDEN;
+    res
+}
+
+/// Reload certificate on SIGHUP and start graceful shutdown on SIGTERM or ctrl-c.
+#[cfg(unix)]
+async fn handle_signals(
+    app_config: Arc<AppConfig>,
+    shutdown: Shutdown,
+    acceptor: Option<Arc<Mutex<TlsAcceptor>>>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to listen SIGHUP");
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen SIGTERM");
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                let acceptor = match &acceptor {
+                    Some(acceptor) => acceptor,
+                    None => {
+                        app_config.logger.info("No certificate to reload without tls");
+                        continue;
+                    }
+                };
+                // error isn't Send and can't be kept across await
+                let reloaded = load_acceptor(&app_config).await.map_err(|e| e.to_string());
+                match reloaded {
+                    Ok(reloaded) => {
+                        *acceptor.lock().await = reloaded;
+                        app_config.logger.info("Certificate reloaded");
+                    }
+                    Err(e) => app_config.logger.err(format!(
+                        "Failed to reload certificate and keep the old one: {}",
+                        e
+                    )),
+                }
+            }
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    start_shutdown(&app_config, &shutdown);
+}
+
+#[cfg(not(unix))]
+async fn handle_signals(
+    app_config: Arc<AppConfig>,
+    shutdown: Shutdown,
+    _: Option<Arc<Mutex<TlsAcceptor>>>,
+) {
+    let _ = tokio::signal::ctrl_c().await;
+    start_shutdown(&app_config, &shutdown);
+}
+
+fn start_shutdown(app_config: &AppConfig, shutdown: &Shutdown) {
+    app_config.logger.info(format!(
+        "Shutting down, waiting for in flight transfers at most {:?}",
+        app_config.shutdown_timeout
+    ));
+    shutdown.draining.cancel();
+}
+
+/// Read certificate, private key and client CA bundle then build the acceptor with them.
+async fn load_acceptor(app_config: &AppConfig) -> Result<TlsAcceptor, Box<dyn Error>> {
+    let (cert_source, key_source) = futures::join!(
+        read_file(&app_config.certificate, "Failed to read certificate file"),
+        read_file(&app_config.private_key, "Failed to read private key file"),
+    );
+    let (cert_source, key_source) = match (cert_source?, key_source?) {
+        (None, None) if !HAS_INTERNAL_CERTIFICATE => {
+            let self_signed = load_self_signed(app_config)?;
+            (Some(self_signed.cert), Some(self_signed.key))
+        }
+        sources => sources,
+    };
+    // none for the built-in certificate
+    let built_in = cert_source.is_none();
+    let certs = load_certs(&cert_source)?;
+    let mut keys = load_keys(&key_source)?;
+    let key = keys.pop().ok_or("SSL private key not found")?;
+    let client_verifier = match &app_config.client_ca {
         Some(path) => {
-            let v = tokio::fs::read(path).await.expect(expect);
-            Some(v)
+            let ca_bundle = tokio::fs::read(path)
+                .await
+                .map_err(|e| format!("Failed to read client CA file: {}", e))?;
+            Some(load_client_verifier(
+                &ca_bundle,
+                app_config.client_cert_required,
+            )?)
         }
         None => None,
+    };
+    let mut resolver = SniResolver::new(certs, &key)?;
+    if let Some(dir) = &app_config.certificate_dir {
+        let names = resolver
+            .load_dir(std::path::Path::new(dir))
+            .map_err(|e| format!("Failed to load certificate directory: {}", e))?;
+        app_config
+            .logger
+            .info(format!("Loaded certificates for {:?}", names));
+    }
+    let first_expiry = resolver.first_expiry(!built_in);
+    let acceptor = build_acceptor(Arc::new(resolver), client_verifier)?;
+    app_config.health.certificate_loaded(first_expiry);
+    Ok(acceptor)
+}
+
+/// Reuse or generate the self-signed certificate for listen host and loopback,
+/// and print its fingerprint for users to verify on first visit.
+fn load_self_signed(app_config: &AppConfig) -> Result<SelfSigned, String> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    for address in app_config.listen_addresses.iter() {
+        match address {
+            ListenAddress::Tcp(address) if !address.ip().is_unspecified() => {
+                names.push(address.ip().to_string())
+            }
+            _ => {}
+        }
+    }
+    for host in app_config.listen_hosts.iter() {
+        let unspecified = host
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_unspecified())
+            .unwrap_or(false);
+        if !host.is_empty() && !unspecified {
+            names.push(host.to_string());
+        }
+    }
+    names.sort();
+    names.dedup();
+
+    let self_signed = load_or_generate_self_signed(&app_config.state_dir, &names)
+        .map_err(|e| format!("Failed to load self-signed certificate: {}", e))?;
+    let fingerprint = load_certs(&Some(self_signed.cert.clone()))
+        .ok()
+        .and_then(|certs| certs.first().map(certificate_fingerprint))
+        .unwrap_or_default();
+    app_config.logger.info(format!(
+        "{} self-signed certificate ({}) for {:?}, SHA-256 fingerprint: {}",
+        match self_signed.generated {
+            true => "Generated",
+            false => "Using",
+        },
+        self_signed.path.display(),
+        names,
+        fingerprint
+    ));
+    Ok(self_signed)
+}
+
+fn build_acceptor(
+    resolver: Arc<SniResolver>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<TlsAcceptor, Box<dyn Error>> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver);
+    config.alpn_protocols = [b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()].into();
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn read_file(path: &Option<String>, expect: &str) -> Result<Option<Vec<u8>>, String> {
+    match path {
+        Some(path) => match tokio::fs::read(path).await {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => Err(format!("{}: {}", expect, e)),
+        },
+        None => Ok(None),
     }
 }
 
@@ -192,6 +628,8 @@ where
 async fn http_websocket_classify(
     context: &AppContext,
     addr: &SocketAddr,
+    https: bool,
+    client_cert: &Option<String>,
     req: Request<hyper::body::Incoming>,
 ) -> Result<ResponseType, Infallible> {
     const UPGRADE_HEADER_VALUE: HeaderValue = HeaderValue::from_static("Upgrade");
@@ -222,10 +660,35 @@ async fn http_websocket_classify(
                         .map(|h| h == "13")
                         .unwrap_or(false)
                 {
+                    if !is_origin_allowed(&context.app_config, headers) {
+                        context.app_config.logger.warn(format!(
+                            "Refuse websocket from {:?} with untrusted origin ({:?}), add it by '--trusted-origin' if it is this server",
+                            addr,
+                            headers.get(header::ORIGIN)
+                        ));
+                        let (_, rx) = mpsc::channel(0);
+                        let mut res = Response::new(StreamBody::new(rx));
+                        *res.status_mut() = StatusCode::FORBIDDEN;
+                        return Ok(res);
+                    }
+                    if is_sign_in_path(&context.app_config, req.uri().path())
+                        && !context.app_config.sign_in_ip_filter.is_allowed(&addr.ip())
+                    {
+                        context.app_config.logger.warn(format!(
+                            "Refuse sign in websocket from {:?} (sign in ip filter)",
+                            addr
+                        ));
+                        let (_, rx) = mpsc::channel(0);
+                        let mut res = Response::new(StreamBody::new(rx));
+                        *res.status_mut() = StatusCode::FORBIDDEN;
+                        return Ok(res);
+                    }
                     let ver = req.version();
                     let context = context.clone();
                     let addr = addr.clone();
-                    tokio::spawn(upgrade_websocket(context, addr, req));
+                    let client_cert = client_cert.clone();
+                    let sessions = context.shutdown.sessions.clone();
+                    sessions.spawn(upgrade_websocket(context, addr, client_cert, req));
 
                     let (_, rx) = mpsc::channel(0);
                     let mut res = Response::new(StreamBody::new(rx));
@@ -251,12 +714,32 @@ async fn http_websocket_classify(
             }
         }
     }
-    return on_http(context, addr, req).await;
+    return on_http(context, addr, https, req).await;
+}
+
+/// Browser always sends origin with websocket and it must be this server or a trusted one.
+/// Request without origin doesn't come from browser and can't be forged by web page.
+/// Host header isn't trusted, page of a rebound domain sends its own name in both.
+fn is_origin_allowed(app_config: &AppConfig, headers: &header::HeaderMap) -> bool {
+    let origin = match headers.get(header::ORIGIN) {
+        Some(origin) => match origin.to_str().map(url::Url::parse) {
+            Ok(Ok(url)) => url.origin(),
+            _ => return false,
+        },
+        None => return true,
+    };
+    // opaque origin ("null") never equals to any origin
+    app_config.trusted_origins.contains(&origin)
+}
+
+fn is_sign_in_path(app_config: &AppConfig, path: &str) -> bool {
+    matches!(app_config.route_path(path), Some("/" | "/rest" | "/rest/"))
 }
 
 async fn upgrade_websocket(
     context: AppContext,
     addr: SocketAddr,
+    client_cert: Option<String>,
     mut req: Request<hyper::body::Incoming>,
 ) {
     let app_config = context.app_config.clone();
@@ -268,7 +751,8 @@ async fn upgrade_websocket(
                 Some(WebSocketConfig::default()),
             )
             .await;
-            if let Err(err) = websocket_server::handle_request(context, &addr, req, ws_stream).await
+            if let Err(err) =
+                websocket_server::handle_request(context, &addr, &client_cert, req, ws_stream).await
             {
                 app_config
                     .logger
@@ -1,42 +0,0 @@
-use tokio_rustls::rustls::{
-    client::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
-    Certificate, DigitallySignedStruct, Error, ServerName,
-};
-
-pub struct CustomServerCertVerifier;
-
-impl ServerCertVerifier for CustomServerCertVerifier {
-    fn verify_server_cert(
-        &self,
-        _: &Certificate,
-        _: &[Certificate],
-        _: &ServerName,
-        _: &mut dyn Iterator<Item = &[u8]>,
-        _: &[u8],
-        _: std::time::SystemTime,
-    ) -> Result<ServerCertVerified, Error> {
-        Ok(ServerCertVerified::assertion())
-    }
-
-    fn verify_tls12_signature(
-        &self,
-        _: &[u8],
-        _: &Certificate,
-        _: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
-
-    fn verify_tls13_signature(
-        &self,
-        _: &[u8],
-        _: &Certificate,
-        _: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
-
-    fn request_scts(&self) -> bool {
-        false
-    }
-}
@@ -1,12 +1,19 @@
 // If this file cause build-failed, checkout readme.md
 
+mod self_signed;
+mod sni_resolver;
+
+pub use self_signed::{load_or_generate as load_or_generate_self_signed, SelfSigned};
+pub use sni_resolver::SniResolver;
+
 use bytes::Buf;
 use rustls_pemfile::{certs, read_one, Item};
 use std::io::{self, ErrorKind};
-use tokio_rustls::rustls::{Certificate, PrivateKey};
-
-mod cert_verifier;
-pub use cert_verifier::CustomServerCertVerifier;
+use std::sync::Arc;
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientCertVerifier,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore};
 
 pub fn load_certs(out: &Option<Vec<u8>>) -> Result<Vec<Certificate>, io::Error> {
     let bytes = match out {
@@ -18,6 +25,12 @@ pub fn load_certs(out: &Option<Vec<u8>>) -> Result<Vec<Certificate>, io::Error>
         .map(|mut certs| certs.drain(..).map(Certificate).collect())
 }
 
+/// Whether both certificate and private key are built in and no self-signed one is needed.
+pub const HAS_INTERNAL_CERTIFICATE: bool = cfg!(all(
+    feature = "internal-certificate",
+    feature = "internal-private-key"
+));
+
 #[cfg(feature = "internal-certificate")]
 fn default_certs() -> &'static [u8] {
     include_bytes!("server.crt")
@@ -61,3 +74,55 @@ fn private_keys(rd: &mut dyn io::BufRead) -> Result<Vec<Vec<u8>>, io::Error> {
         };
     }
 }
+
+/// Verify client certificate against the CA bundle.
+/// Client without certificate is still accepted unless `required`.
+pub fn load_client_verifier(
+    ca_bundle: &[u8],
+    required: bool,
+) -> Result<Arc<dyn ClientCertVerifier>, io::Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&Some(ca_bundle.to_vec()))? {
+        roots
+            .add(&cert)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "invalid client ca cert"))?;
+    }
+    if roots.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "client ca cert not found",
+        ));
+    }
+    Ok(match required {
+        true => AllowAnyAuthenticatedClient::new(roots).boxed(),
+        false => AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+    })
+}
+
+/// Subject common name of certificate, empty if the certificate has no common name.
+pub fn certificate_common_name(cert: &Certificate) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    let common_name = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .unwrap_or_default();
+    Some(common_name.to_string())
+}
+
+/// Expiry (unix timestamp) of certificate, None if it can't be parsed.
+pub fn certificate_not_after(cert: &Certificate) -> Option<i64> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    Some(cert.validity().not_after.timestamp())
+}
+
+/// SHA-256 fingerprint of certificate in the colon separated hex form browsers show.
+pub fn certificate_fingerprint(cert: &Certificate) -> String {
+    ring::digest::digest(&ring::digest::SHA256, &cert.0)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,120 @@
+use chrono::{Datelike, Utc};
+use rcgen::{CertificateParams, DistinguishedName, DnType, SanType};
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+const CERT_FILE: &str = "self-signed.crt";
+const KEY_FILE: &str = "self-signed.key";
+const VALID_YEARS: i32 = 10;
+
+pub struct SelfSigned {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+    pub path: PathBuf,
+    pub generated: bool,
+}
+
+/// Reuse the self-signed certificate kept in the state directory,
+/// or generate a new one if it is missing, expired or doesn't cover every name.
+pub fn load_or_generate(dir: &Path, names: &[String]) -> Result<SelfSigned, io::Error> {
+    let cert_path = dir.join(CERT_FILE);
+    let key_path = dir.join(KEY_FILE);
+    if let (Ok(cert), Ok(key)) = (std::fs::read(&cert_path), std::fs::read(&key_path)) {
+        if covers(&cert, names) {
+            return Ok(SelfSigned {
+                cert,
+                key,
+                path: cert_path,
+                generated: false,
+            });
+        }
+    }
+
+    let (cert, key) = generate(names)?;
+    std::fs::create_dir_all(dir)?;
+    write_private(&key_path, &key)?;
+    std::fs::write(&cert_path, &cert)?;
+    Ok(SelfSigned {
+        cert,
+        key,
+        path: cert_path,
+        generated: true,
+    })
+}
+
+fn generate(names: &[String]) -> Result<(Vec<u8>, Vec<u8>), io::Error> {
+    let mut params = CertificateParams::default();
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, "web-ssh-tool self-signed");
+    params.distinguished_name = distinguished_name;
+    params.subject_alt_names = names
+        .iter()
+        .map(|name| match name.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name.clone()),
+        })
+        .collect();
+    let today = Utc::now().date_naive();
+    let (month, day) = (today.month() as u8, today.day() as u8);
+    params.not_before = rcgen::date_time_ymd(today.year(), month, day);
+    // february 29th doesn't exist ten years later in most cases
+    params.not_after = rcgen::date_time_ymd(today.year() + VALID_YEARS, month, day.min(28));
+
+    let cert = rcgen::Certificate::from_params(params).map_err(io::Error::other)?;
+    let cert_pem = cert.serialize_pem().map_err(io::Error::other)?;
+    let key_pem = cert.serialize_private_key_pem();
+    Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
+}
+
+/// Whether the pem certificate is still valid and has every name in its subject alternative names.
+fn covers(cert_pem: &[u8], names: &[String]) -> bool {
+    let der = match super::load_certs(&Some(cert_pem.to_vec())) {
+        Ok(mut certs) if !certs.is_empty() => certs.remove(0),
+        _ => return false,
+    };
+    let cert = match x509_parser::parse_x509_certificate(&der.0) {
+        Ok((_, cert)) => cert,
+        Err(_) => return false,
+    };
+    if !cert.validity().is_valid() {
+        return false;
+    }
+    let sans = match cert.subject_alternative_name() {
+        Ok(Some(extension)) => &extension.value.general_names,
+        _ => return false,
+    };
+    names.iter().all(|name| {
+        let ip = name.parse::<IpAddr>().ok();
+        sans.iter().any(|san| match (san, ip) {
+            (x509_parser::extensions::GeneralName::DNSName(dns), None) => {
+                dns.eq_ignore_ascii_case(name)
+            }
+            (x509_parser::extensions::GeneralName::IPAddress(bytes), Some(ip)) => match ip {
+                IpAddr::V4(ip) => *bytes == ip.octets(),
+                IpAddr::V6(ip) => *bytes == ip.octets(),
+            },
+            _ => false,
+        })
+    })
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, content: &[u8]) -> Result<(), io::Error> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // mode only applies to a new file, an existing one keeps its own
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(content)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, content: &[u8]) -> Result<(), io::Error> {
+    std::fs::write(path, content)
+}
@@ -0,0 +1,174 @@
+use super::{certificate_common_name, certificate_not_after, load_certs, private_keys};
+use p12_keystore::{KeyStore, Pkcs12ImportPolicy};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey};
+
+/// Pick certificate by the server name (SNI) client asks for,
+/// the default one for unknown names and clients without SNI (e.g. visiting by IP).
+pub struct SniResolver {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    pub fn new(certs: Vec<Certificate>, key: &PrivateKey) -> Result<Self, io::Error> {
+        Ok(Self {
+            default: certified_key(certs, key)?,
+            by_name: HashMap::new(),
+        })
+    }
+
+    /// Load every `<name>.crt` (or `.pem`/`.cer`) with its `<name>.key`, and every `<name>.p12`
+    /// (or `.pfx`) bundle with the password in `<name>.pass` if any, from the directory.
+    /// Certificate serves the DNS names in its subject alternative names (common name if none).
+    /// Return names loaded.
+    pub fn load_dir(&mut self, dir: &Path) -> Result<Vec<String>, io::Error> {
+        let mut loaded = Vec::new();
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for path in entries {
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            let pair = match extension.to_ascii_lowercase().as_str() {
+                "crt" | "pem" | "cer" => load_pair(&path),
+                "p12" | "pfx" => load_pkcs12(&path),
+                _ => continue,
+            };
+            let (certs, key) =
+                pair.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            let names = certificate_dns_names(&certs[0]);
+            if names.is_empty() {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: no dns name in certificate", path.display()),
+                ));
+            }
+            let certified = certified_key(certs, &key)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            for name in names {
+                self.by_name.insert(name.clone(), certified.clone());
+                loaded.push(name);
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Common name and expiry (unix timestamp) of the leaf certificate that expires first,
+    /// the default one is left out if it's the built-in certificate rather than a loaded one.
+    pub fn first_expiry(&self, with_default: bool) -> Option<(String, i64)> {
+        with_default
+            .then_some(&self.default)
+            .into_iter()
+            .chain(self.by_name.values())
+            .filter_map(|certified| {
+                let cert = certified.cert.first()?;
+                let not_after = certificate_not_after(cert)?;
+                Some((certificate_common_name(cert).unwrap_or_default(), not_after))
+            })
+            .min_by_key(|(_, not_after)| *not_after)
+    }
+
+    fn find(&self, name: &str) -> Option<&Arc<CertifiedKey>> {
+        let name = name.to_ascii_lowercase();
+        self.by_name.get(&name).or_else(|| {
+            let (_, parent) = name.split_once('.')?;
+            self.by_name.get(&format!("*.{}", parent))
+        })
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certified = client_hello
+            .server_name()
+            .and_then(|name| self.find(name))
+            .unwrap_or(&self.default);
+        Some(certified.clone())
+    }
+}
+
+fn certified_key(
+    certs: Vec<Certificate>,
+    key: &PrivateKey,
+) -> Result<Arc<CertifiedKey>, io::Error> {
+    let signing_key = any_supported_type(key)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "unsupported private key type"))?;
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
+
+/// Certificate with the private key of same file stem, both in pem or der.
+fn load_pair(cert_path: &Path) -> Result<(Vec<Certificate>, PrivateKey), io::Error> {
+    let cert_bytes = std::fs::read(cert_path)?;
+    let certs = match load_certs(&Some(cert_bytes.clone()))? {
+        certs if certs.is_empty() => vec![Certificate(cert_bytes)],
+        certs => certs,
+    };
+    let key_path = cert_path.with_extension("key");
+    let key_bytes = std::fs::read(&key_path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", key_path.display(), e)))?;
+    let key = match private_keys(&mut key_bytes.as_slice())?.pop() {
+        Some(key) => key,
+        None => key_bytes,
+    };
+    Ok((certs, PrivateKey(key)))
+}
+
+fn load_pkcs12(path: &Path) -> Result<(Vec<Certificate>, PrivateKey), io::Error> {
+    let bundle = std::fs::read(path)?;
+    let password = match std::fs::read_to_string(path.with_extension("pass")) {
+        Ok(password) => password.trim_end_matches(['\r', '\n']).to_string(),
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let key_store = KeyStore::from_pkcs12(&bundle, &password, Pkcs12ImportPolicy::Relaxed)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("invalid pkcs12 ({})", e)))?;
+    let (_, chain) = key_store
+        .private_key_chain()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "private key not found"))?;
+    let certs = chain
+        .certs()
+        .iter()
+        .map(|cert| Certificate(cert.as_der().to_vec()))
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "certificate not found",
+        ));
+    }
+    Ok((certs, PrivateKey(chain.key().as_der().to_vec())))
+}
+
+/// Lowercase dns names of certificate, wildcard name is kept as `*.example.com`.
+fn certificate_dns_names(cert: &Certificate) -> Vec<String> {
+    let cert = match x509_parser::parse_x509_certificate(&cert.0) {
+        Ok((_, cert)) => cert,
+        Err(_) => return Vec::new(),
+    };
+    let mut names = match cert.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                x509_parser::extensions::GeneralName::DNSName(dns) => Some(dns.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+    if names.is_empty() {
+        names.extend(
+            cert.subject()
+                .iter_common_name()
+                .filter_map(|cn| cn.as_str().ok().map(|s| s.to_string())),
+        );
+    }
+    names.iter().map(|name| name.to_ascii_lowercase()).collect()
+}
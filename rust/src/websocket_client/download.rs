@@ -1,14 +1,10 @@
-use super::components::{file_to_stream, http_to_master, ArgumentsError, BUF_SIZE};
-use crate::common::{app_config::AppConfig, ResponseUnit};
+use super::components::{file_to_stream, ArgumentsError, BUF_SIZE};
+use crate::common::{app_config::AppConfig, pipe::Pipe, ResponseUnit};
 use async_compat::CompatExt;
 use async_zip::{base::write::ZipFileWriter, error::ZipError, Compression, ZipEntryBuilder};
 use bytes::Bytes;
-use futures::{
-    channel::{mpsc, oneshot},
-    join, AsyncWrite, Sink,
-};
-use http_body_util::{BodyExt, StreamBody};
-use hyper::{body::Frame, header, http::HeaderValue, HeaderMap, Method, Request};
+use futures::{channel::mpsc, join, AsyncWrite, Sink};
+use hyper::{body::Frame, header, http::HeaderValue, HeaderMap};
 use serde_json::json;
 use std::{
     convert::Infallible,
@@ -20,7 +16,7 @@ use walkdir::WalkDir;
 
 pub async fn handle_request(
     app_config: &Arc<AppConfig>,
-    token: &String,
+    pipe: &Pipe,
     id: u64,
     argument: &serde_json::Value,
 ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
@@ -35,34 +31,18 @@ pub async fn handle_request(
     if paths.len() == 0 {
         return Err(Box::new(ArgumentsError(Some(format!("no paths")))));
     }
-    let (mut parts, _) = Request::new("").into_parts();
-    let headers = &mut parts.headers;
-    headers.append("id", HeaderValue::from_str(id.to_string().as_str())?);
-    headers.append("peer", HeaderValue::from_str(token.as_str())?);
+    let mut headers = HeaderMap::new();
     headers.append(
         header::CONTENT_TYPE,
         HeaderValue::from_static("application/octet-stream"),
     );
-    headers.append(header::CONNECTION, HeaderValue::from_static("close"));
-    let (rx, on_end) = match paths.len() {
-        1 => download_single(app_config, paths[0].to_owned(), headers).await?,
-        _ => download_multi(app_config, paths, headers).await?,
+    let rx = match paths.len() {
+        1 => download_single(app_config, paths[0].to_owned(), &mut headers).await?,
+        _ => download_multi(app_config, paths, &mut headers).await?,
     };
 
-    let mut sender = http_to_master(app_config).await?;
-    let body = StreamBody::new(rx);
-    let mut req = Request::from_parts(parts, body);
-    *req.uri_mut() = "/client".parse()?;
-    *req.method_mut() = Method::PUT;
-    let mut response = sender.send_request(req).await?;
-    tokio::spawn(async move {
-        let body = response.body_mut();
-        while let Some(Ok(_)) = body.frame().await {}
-    });
-    tokio::spawn(async move {
-        let _ = on_end.await;
-        let _ = sender; // prevent socket from closing when sending files
-    });
+    let stream = pipe.open(id, headers).await?;
+    tokio::spawn(stream.sink.forward(rx));
     Ok(json!(null))
 }
 
@@ -70,7 +50,7 @@ pub async fn download_single(
     app_config: &Arc<AppConfig>,
     path: String,
     headers: &mut HeaderMap,
-) -> Result<(mpsc::Receiver<ResponseUnit>, oneshot::Receiver<()>), ArgumentsError> {
+) -> Result<mpsc::Receiver<ResponseUnit>, ArgumentsError> {
     let p = Path::new(path.as_str()).to_path_buf();
     let p = if p.is_symlink() {
         if let Ok(buf) = tokio::fs::read_link(p).await {
@@ -88,7 +68,6 @@ pub async fn download_single(
         },
         None => None,
     };
-    let (on_end_callback, on_end) = oneshot::channel();
     if let Some(file_name) = file_name {
         if p.is_file() {
             if let Ok(file) = tokio::fs::File::open(p.as_path()).await {
@@ -114,7 +93,7 @@ pub async fn download_single(
                     )
                 };
                 headers.append(key, value);
-                return Ok((file_to_stream(file, on_end_callback), on_end));
+                return Ok(file_to_stream(file));
             }
         } else if p.is_dir() {
             let (tx, rx) = mpsc::channel(BUF_SIZE);
@@ -125,7 +104,6 @@ pub async fn download_single(
                     let error = format!("ZipError: {:?}", e);
                     app_config.logger.err(error);
                 }
-                let _ = on_end_callback.send(());
             });
             if let Ok(disposition) = HeaderValue::from_str(
                 format!("attachment; filename=\"{}.zip\";", file_name).as_str(),
@@ -136,7 +114,7 @@ pub async fn download_single(
                 header::TRANSFER_ENCODING,
                 HeaderValue::from_static("chunked"),
             );
-            return Ok((rx, on_end));
+            return Ok(rx);
         }
     }
     return Err(ArgumentsError(None));
@@ -162,8 +140,7 @@ async fn download_multi(
     app_config: &Arc<AppConfig>,
     paths: Vec<String>,
     headers: &mut HeaderMap,
-) -> Result<(mpsc::Receiver<ResponseUnit>, oneshot::Receiver<()>), Infallible> {
-    let (on_end_callback, on_end) = oneshot::channel();
+) -> Result<mpsc::Receiver<ResponseUnit>, Infallible> {
     let (tx, rx) = mpsc::channel(BUF_SIZE);
     let app_config = app_config.clone();
     tokio::spawn(async move {
@@ -171,7 +148,6 @@ async fn download_multi(
             let error = format!("ZipError: {:?}", e);
             app_config.logger.err(error);
         }
-        let _ = on_end_callback.send(());
     });
     if let Ok(disposition) =
         HeaderValue::from_str(format!("attachment; filename=\"bundle.zip\";").as_str())
@@ -182,7 +158,7 @@ async fn download_multi(
         header::TRANSFER_ENCODING,
         HeaderValue::from_static("chunked"),
     );
-    return Ok((rx, on_end));
+    Ok(rx)
 }
 
 async fn zip_multi(
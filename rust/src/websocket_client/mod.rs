@@ -6,79 +6,57 @@ mod unzip;
 mod upload;
 mod watch;
 
-use crate::common::app_config::AppConfig;
+use crate::common::{
+    app_config::AppConfig,
+    pipe::{Pipe, PipeEvent},
+};
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
     lock::Mutex,
-    stream::SplitSink,
-    SinkExt, StreamExt,
+    StreamExt,
 };
 use serde_json::json;
 use std::{collections::HashMap, error::Error, sync::Arc};
-use tokio::net::TcpStream;
-use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
 use self::components::ArgumentsError;
 
-pub async fn handle_request(
-    app_config: &Arc<AppConfig>,
-    token: &String,
-    ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
-) -> Result<(), Box<dyn Error>> {
-    let (write, read) = ws_stream.split();
-    let write = Arc::new(Mutex::new(write));
+/// Serve master over stdin and stdout until master closes stdin.
+pub async fn handle_request(app_config: &Arc<AppConfig>) -> Result<(), Box<dyn Error>> {
+    let (pipe, events) = Pipe::serve(tokio::io::stdin(), tokio::io::stdout()).await?;
     let watchers = Mutex::new(HashMap::new());
     let (tx, rx) = channel(0); // event_channel
-    tokio::spawn(poll_event(rx, write.clone()));
+    tokio::spawn(poll_event(rx, pipe.clone()));
 
-    read.for_each_concurrent(None, |data| async {
-        if let Ok(Message::Text(text)) = data {
+    events
+        .for_each_concurrent(None, |event| async {
             fn handle_error(err: Box<dyn std::error::Error>) -> serde_json::Value {
                 let result = format!("{:?}", err);
                 json!({ "error": result })
             }
-            if let Ok(serde_json::Value::Object(mut m)) =
-                serde_json::from_str::<serde_json::Value>(text.as_str())
-            {
+            if let PipeEvent::Message(serde_json::Value::Object(mut m)) = event {
                 if let (Some(id), Some(serde_json::Value::Object(request))) =
                     (m.remove("id"), m.remove("request"))
                 {
                     for (key, value) in request.iter() {
-                        let result = handle_call(app_config, token, key, value, &tx, &watchers)
+                        let result = handle_call(app_config, &pipe, key, value, &tx, &watchers)
                             .await
                             .unwrap_or_else(handle_error);
-                        let mut write = write.lock().await;
-                        let _ = write
-                            .send(Message::text(
-                                json!({"id":id, "response":result}).to_string(),
-                            ))
-                            .await;
+                        let _ = pipe.send_message(json!({"id":id, "response":result})).await;
                         return;
                     }
                 }
             }
-        }
-        let mut write = write.lock().await;
-        let _ = write
-            .send(Message::text(
-                json!({"error": "message parse failed"}).to_string(),
-            ))
-            .await;
-    })
-    .await;
+            let _ = pipe
+                .send_message(json!({"error": "message parse failed"}))
+                .await;
+        })
+        .await;
     Ok(())
 }
 
-async fn poll_event(
-    mut rx: Receiver<serde_json::Value>,
-    tx: Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>,
-) {
+async fn poll_event(mut rx: Receiver<serde_json::Value>, pipe: Pipe) {
     while let Some(e) = rx.next().await {
-        let mut tx = tx.lock().await;
-        if let Err(_) = tx
-            .send(Message::Text(json!({ "event": e }).to_string()))
-            .await
-        {
+        if pipe.send_message(json!({ "event": e })).await.is_err() {
             break;
         }
     }
@@ -86,7 +64,7 @@ async fn poll_event(
 
 async fn handle_call(
     app_config: &Arc<AppConfig>,
-    token: &String,
+    pipe: &Pipe,
     call: &String,
     argument: &serde_json::Value,
     event_channel: &Sender<serde_json::Value>,
@@ -104,14 +82,14 @@ async fn handle_call(
         "fs.trash" => fs_api::fs_trash(argument).await,
         "unzip" => unzip::handle_request(argument).await,
         "watch" => watch::handle_request(argument, event_channel, watchers).await,
-        "internal" => handle_internal(app_config, token, argument).await,
+        "internal" => handle_internal(app_config, pipe, argument).await,
         _ => Err(Box::new(Unimplemented())),
     }
 }
 
 async fn handle_internal(
     app_config: &Arc<AppConfig>,
-    token: &String,
+    pipe: &Pipe,
     argument: &serde_json::Value,
 ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
     if let serde_json::Value::Array(argument) = argument {
@@ -123,16 +101,14 @@ async fn handle_internal(
                     for (key, argument) in argument.iter() {
                         match key.as_str() {
                             "download" => {
-                                return download::handle_request(app_config, token, id, argument)
+                                return download::handle_request(app_config, pipe, id, argument)
                                     .await;
                             }
                             "upload" => {
-                                return upload::handle_request(app_config, token, id, argument)
-                                    .await;
+                                return upload::handle_request(pipe, id, argument).await;
                             }
                             "preview" => {
-                                return preview::handle_request(app_config, token, id, argument)
-                                    .await;
+                                return preview::handle_request(pipe, id, argument).await;
                             }
                             _ => {}
                         }
@@ -1,16 +1,13 @@
-use super::components::{http_to_master, ArgumentsError};
-use crate::common::app_config::AppConfig;
+use super::components::ArgumentsError;
+use crate::common::pipe::Pipe;
 use bytes::Bytes;
-use futures::SinkExt;
-use http_body_util::{BodyExt, StreamBody};
-use hyper::{body::Frame, header, http::HeaderValue, Method, Request};
+use hyper::{header, http::HeaderValue, HeaderMap};
 use serde_json::json;
-use std::{path::Path, sync::Arc};
+use std::path::Path;
 use tokio::io::AsyncWriteExt;
 
 pub async fn handle_request(
-    app_config: &Arc<AppConfig>,
-    token: &String,
+    pipe: &Pipe,
     id: u64,
     argument: &serde_json::Value,
 ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
@@ -30,13 +27,6 @@ pub async fn handle_request(
     }
     match argus {
         Some((dir_str, filename)) => {
-            use futures::channel::mpsc::channel;
-            let (mut tx, rx) = channel(0);
-            let body = StreamBody::new(rx);
-            let mut req = Request::new(body);
-            *req.uri_mut() = "/client".parse()?;
-            *req.method_mut() = Method::PUT;
-            let headers = req.headers_mut();
             let dir = Path::new(dir_str.as_str());
             let temp = match filename {
                 Some(filename) => filename.to_string(),
@@ -46,11 +36,7 @@ pub async fn handle_request(
                 }
             };
             let file_path = dir.join(temp.clone());
-            let (sender, file) = tokio::join!(
-                http_to_master(app_config),
-                tokio::fs::File::create(file_path.clone()),
-            );
-            let mut sender = sender?;
+            let file = tokio::fs::File::create(file_path.clone()).await;
             let bytes = match &file {
                 Ok(_) => {
                     let file_path = match file_path.to_str() {
@@ -74,8 +60,7 @@ pub async fn handle_request(
                 }
             };
 
-            headers.append("id", HeaderValue::from_str(id.to_string().as_str())?);
-            headers.append("peer", HeaderValue::from_str(token.as_str())?);
+            let mut headers = HeaderMap::new();
             headers.append(
                 header::CONTENT_TYPE,
                 HeaderValue::from_str(mime_guess::mime::TEXT_PLAIN.to_string().as_str())?,
@@ -84,28 +69,21 @@ pub async fn handle_request(
                 header::CONTENT_LENGTH,
                 HeaderValue::from_str(bytes.len().to_string().as_str())?,
             );
-            headers.append(header::CONNECTION, HeaderValue::from_static("close"));
 
-            let mut response = sender.send_request(req).await?;
+            let mut stream = pipe.open(id, headers).await?;
 
-            if let Ok(mut file) = file {
-                // @TODO: maybe delete file if any error occurred
-                tokio::spawn(async move {
-                    let body = response.body_mut();
-                    while let Some(Ok(mut frame)) = body.frame().await {
-                        if let Some(data) = frame.data_mut() {
-                            let vec = data.to_vec();
-                            if let Err(_) = file.write_all(&vec[..]).await {
-                                break;
-                            }
+            // @TODO: maybe delete file if any error occurred
+            tokio::spawn(async move {
+                if let Ok(mut file) = file {
+                    while let Some(data) = stream.source.next().await {
+                        if file.write_all(&data[..]).await.is_err() {
+                            break;
                         }
                     }
-                    // only response body after receive full file
-                    // if send body before receive full file, the socket will be closed unexpectedly
-                    let _ = tx.send(Ok(Frame::data(bytes))).await;
-                    let _ = sender; // prevent socket from closing
-                });
-            }
+                }
+                // only response body after receive full file
+                let _ = stream.sink.send(bytes).await;
+            });
 
             Ok(json!(null))
         }
@@ -1,6 +1,7 @@
 mod on_authenticate;
 mod on_client;
 mod on_request_authenticate;
+pub mod session;
 mod shell;
 use crate::common::AppContext;
 use flate2::write::{GzDecoder, GzEncoder};
@@ -14,31 +15,15 @@ use tokio_tungstenite::WebSocketStream;
 pub async fn handle_request(
     context: AppContext,
     addr: &SocketAddr,
+    client_cert: &Option<String>,
     req: Request<hyper::body::Incoming>,
     ws_stream: WebSocketStream<Upgraded>,
 ) -> Result<(), Box<dyn Error>> {
     let app_config = context.app_config.clone();
-    match req.uri().path() {
-        "/" | "/rest" | "/rest/" => {
-            on_request_authenticate::handle_request(context, addr, req, ws_stream).await
-        }
-        "/client" => {
-            let is_loopback = match addr {
-                SocketAddr::V4(v4) => v4.ip().is_loopback(),
-                SocketAddr::V6(v6) => v6.ip().is_loopback(),
-            };
-            if let (Some(query), true) = (req.uri().query(), is_loopback) {
-                use url::form_urlencoded::parse;
-                let mut peers = parse(query.as_bytes()).into_owned();
-                if let Some((_, token)) = peers.find(|(key, _)| key.as_str() == "t") {
-                    on_client::handle_request(context, ws_stream, token.as_str()).await?;
-                    return Ok(());
-                }
-            }
-            app_config
-                .logger
-                .err(format!("Unknown client ws connection ({})", req.uri()));
-            Ok(())
+    match app_config.route_path(req.uri().path()) {
+        Some("/" | "/rest" | "/rest/") => {
+            on_request_authenticate::handle_request(context, addr, client_cert, req, ws_stream)
+                .await
         }
         _ => {
             app_config
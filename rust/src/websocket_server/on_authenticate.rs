@@ -1,39 +1,61 @@
 use super::encode_value;
 use super::internal_decompress;
+use super::session::SessionState;
 use super::shell;
-use super::shell::PollChannelData;
-use crate::common::websocket_peer::{Client, ClientWebsocket};
-use futures::{
-    channel::{mpsc, oneshot},
-    lock::Mutex,
-    stream::SplitSink,
-    SinkExt, StreamExt,
-};
+use crate::common::app_config::AppConfig;
+use crate::common::audit::{call_fields, is_mutating_call};
+use crate::common::websocket_peer::{ClientConnection, SessionClock, SessionExpired};
+use crate::common::AppContext;
+use futures::{channel::oneshot, lock::Mutex, stream::SplitSink, SinkExt, StreamExt};
 use hyper::upgrade::Upgraded;
-use russh::{self, client::Handle};
 use serde_json::json;
-use std::{collections::HashMap, error::Error, sync::Arc};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use std::{
+    error::Error,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
+};
 
 pub async fn handle_request(
+    context: &AppContext,
+    addr: &SocketAddr,
     token: &String,
-    client_connection: &Arc<Mutex<ClientWebsocket>>,
+    client_connection: &Arc<Mutex<ClientConnection>>,
     ws_stream: WebSocketStream<Upgraded>,
-    event_channel: mpsc::Receiver<serde_json::Value>,
-    session: Handle<Client>,
-) -> Result<(), Box<dyn Error>> {
+    state: &SessionState,
+    clock: &SessionClock,
+) -> Result<SessionEnd, Box<dyn Error>> {
+    let app_config = &context.app_config;
+    let shutdown = &context.shutdown;
     let (write, read) = ws_stream.split();
     let write = Arc::new(Mutex::new(write));
-    let session = Mutex::new(session);
-    let shells = Mutex::new(HashMap::new());
-    tokio::spawn(poll_event(event_channel, write.clone()));
-    read.for_each_concurrent(16, |data| async {
+    let signed_out = AtomicBool::new(false);
+    let requests = read.for_each_concurrent(16, |data| async {
+        if let Ok(Message::Text(_) | Message::Binary(_)) = &data {
+            clock.touch().await;
+        }
         let text = match data {
             Ok(Message::Text(t)) => t,
             Ok(Message::Binary(bytes)) => match internal_decompress(&bytes[..]) {
                 Ok(t) => t,
                 Err(_) => return,
             },
+            Ok(Message::Close(Some(frame))) => {
+                // browser signs out with normal closure, otherwise it may come back
+                if frame.code == CloseCode::Normal {
+                    signed_out.store(true, Ordering::Relaxed);
+                }
+                return;
+            }
             Ok(Message::Ping(bytes)) => {
                 let mut write = write.lock().await;
                 let _ = write.send(Message::Pong(bytes)).await;
@@ -47,7 +69,7 @@ pub async fn handle_request(
             let tag = m.remove("tag");
             let request = m.remove("request");
             let response =
-                build_response(token, request, &client_connection, &session, &shells).await;
+                build_response(app_config, addr, token, request, client_connection, state).await;
 
             let message = match response {
                 Ok(response) => {
@@ -68,46 +90,94 @@ pub async fn handle_request(
         let msg = encode_value(json!({"error": "message format error"}));
         let mut write = write.lock().await;
         let _ = write.send(msg).await;
-    })
-    .await;
+    });
+
+    tokio::select! {
+        _ = requests => match signed_out.load(Ordering::Relaxed) {
+            true => Ok(SessionEnd::SignedOut),
+            false => Ok(SessionEnd::Disconnected),
+        },
+        _ = poll_event(state, write.clone()) => Ok(SessionEnd::Disconnected),
+        reason = clock.expired() => {
+            let msg = encode_value(json!({"event": {"sessionExpired": reason.to_string()}}));
+            let mut write = write.lock().await;
+            let _ = write.send(msg).await;
+            let _ = write.close().await;
+            Ok(SessionEnd::Expired(reason))
+        }
+        _ = async {
+            shutdown.draining.cancelled().await;
+            let msg = encode_value(json!({"event": {"notification": "Server is shutting down"}}));
+            let _ = write.lock().await.send(msg).await;
+            shutdown.closing.cancelled().await;
+        } => {
+            let mut write = write.lock().await;
+            let frame = CloseFrame {
+                code: CloseCode::Away,
+                reason: "server shutdown".into(),
+            };
+            let _ = write.send(Message::Close(Some(frame))).await;
+            let _ = write.close().await;
+            Ok(SessionEnd::Shutdown)
+        }
+    }
+}
 
-    Ok(())
+pub enum SessionEnd {
+    Disconnected,
+    SignedOut,
+    Expired(SessionExpired),
+    Shutdown,
 }
 
 #[derive(Debug)]
 enum RequestError {
     UnknownRequest,
     SshConnectNotEstablish,
+    FileSystemUnavailable,
     InternalError,
 }
 
+/// Forward events to browser until the websocket broken.
 async fn poll_event(
-    mut event_channel: mpsc::Receiver<serde_json::Value>,
+    state: &SessionState,
     tx: Arc<Mutex<SplitSink<WebSocketStream<Upgraded>, Message>>>,
 ) {
+    let mut event_channel = state.events.lock().await;
     while let Some(event) = event_channel.next().await {
-        let msg = encode_value(json!({ "event": event }));
+        let msg = encode_value(json!({ "event": &event }));
         let mut tx = tx.lock().await;
         if let Err(_) = tx.send(msg).await {
-            break;
+            // keep it for the browser that may resume the session
+            state.park_event(event).await;
+            return;
         }
     }
+    // event channel closed but requests still can be served
+    futures::future::pending().await
 }
 
 async fn build_response(
+    app_config: &Arc<AppConfig>,
+    addr: &SocketAddr,
     token: &String,
     request: Option<serde_json::Value>,
-    client_connection: &Arc<Mutex<ClientWebsocket>>,
-    session: &Mutex<Handle<Client>>,
-    shells: &Mutex<HashMap<String, mpsc::Sender<PollChannelData>>>,
+    client_connection: &Arc<Mutex<ClientConnection>>,
+    state: &SessionState,
 ) -> Result<serde_json::Value, RequestError> {
     if let Some(serde_json::Value::Object(request)) = request {
         for (key, value) in request {
             match key.as_str() {
                 "internal" => {}
                 "shell" => {
-                    return match shell::handle_request(value, &client_connection, &session, &shells)
-                        .await
+                    return match shell::handle_request(
+                        app_config,
+                        addr,
+                        value,
+                        client_connection,
+                        state,
+                    )
+                    .await
                     {
                         Ok(_) => Ok(serde_json::Value::Null),
                         Err(_) => Err(RequestError::InternalError),
@@ -121,19 +191,27 @@ async fn build_response(
 
                     {
                         let mut conn = client_connection.lock().await;
+                        if !conn.is_available() {
+                            return Err(RequestError::FileSystemUnavailable);
+                        }
                         if let Err(_) = conn.send_request(json!({key: value}), tx).await {
                             return Err(RequestError::SshConnectNotEstablish);
                         }
                     }
 
-                    match rx.await {
-                        Ok(value) => {
-                            return Ok(value);
-                        }
-                        Err(_) => {
-                            return Err(RequestError::InternalError);
-                        }
+                    let response = rx.await.map_err(|_| RequestError::InternalError);
+                    if is_mutating_call(key) {
+                        let mut record = call_fields(key, &value);
+                        record["result"] = match &response {
+                            Ok(serde_json::Value::Object(m)) if m.contains_key("error") => {
+                                json!("failure")
+                            }
+                            Ok(_) => json!("success"),
+                            Err(_) => json!("failure"),
+                        };
+                        state.auditor.record(addr, "file", record);
                     }
+                    return response;
                 }
             }
         }
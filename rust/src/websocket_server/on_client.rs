@@ -1,75 +1,66 @@
-use crate::common::{websocket_peer::ClientWebsocket, AppContext};
-use futures::{lock::Mutex, StreamExt};
-use hyper::upgrade::Upgraded;
-use std::{error::Error, sync::Arc};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use crate::common::app_config::AppConfig;
+use crate::common::logger::{session_id, Level};
+use crate::common::pipe::PipeEvent;
+use crate::common::websocket_peer::{ClientConnection, ClientHttp};
+use futures::{channel::mpsc, lock::Mutex, StreamExt};
+use std::sync::Arc;
 
+/// Serve messages and streams from internal client until its pipe closed.
 pub async fn handle_request(
-    context: AppContext,
-    ws_stream: WebSocketStream<Upgraded>,
-    token: &str,
-) -> Result<(), Box<dyn Error>> {
-    let app_config = &context.app_config;
-    let suspended_clients = &context.suspended_clients;
-    let result = {
-        let mut map = suspended_clients.lock().await;
-        map.remove(token)
-    };
-    if let Some((callback, event_channel)) = result {
-        let (write, read) = ws_stream.split();
-        let client_connection = Arc::new(Mutex::new(ClientWebsocket::new(event_channel, write)));
-        if let Err(_) = callback.send(client_connection.clone()) {
-            app_config.logger.err(format!(
-                "Not found token({}) in current connecting peers",
-                token
-            ));
-            return Ok(());
-        }
-
+    app_config: Arc<AppConfig>,
+    token: String,
+    client_connection: Arc<Mutex<ClientConnection>>,
+    client_http: Arc<Mutex<ClientHttp>>,
+    events: mpsc::Receiver<PipeEvent>,
+) {
+    let session = session_id(&token);
+    let log = |level: Level, message: String| {
         app_config
             .logger
-            .info(format!("Internal client for token({}) connected", token));
-        read.for_each_concurrent(None, |data| async {
-            if let Ok(Message::Text(text)) = data {
-                if let Ok(serde_json::Value::Object(mut m)) =
-                    serde_json::from_str::<serde_json::Value>(text.as_str())
-                {
+            .event(level, message)
+            .field("session", &session)
+            .emit()
+    };
+    log(Level::Info, "Internal client connected".to_string());
+    events
+        .for_each_concurrent(None, |event| async {
+            match event {
+                PipeEvent::Message(serde_json::Value::Object(mut m)) => {
                     let mut conn = client_connection.lock().await;
                     if let (Some(id), Some(response)) = (m.remove("id"), m.remove("response")) {
                         if let Err(err) = conn.feed_response(id, response) {
-                            app_config.logger.info(format!(
-                                "Internal client for token({}) failed to feed response ({:?})",
-                                token, err
-                            ));
+                            log(
+                                Level::Warn,
+                                format!("Internal client failed to feed response ({:?})", err),
+                            );
                         }
-                        return;
                     } else if let Some(event) = m.remove("event") {
                         if let Err(err) = conn.forward_event(event).await {
-                            app_config.logger.info(format!(
-                                "Internal client for token({}) failed to forward event ({:?})",
-                                token, err
-                            ));
+                            log(
+                                Level::Warn,
+                                format!("Internal client failed to forward event ({:?})", err),
+                            );
                         }
-                        return;
+                    } else {
+                        log(
+                            Level::Error,
+                            format!("Unknown message from internal client: {:?}", m),
+                        );
                     }
                 }
-                app_config.logger.err(format!(
-                    "Unknown message from client  for token({}): {}",
-                    token, text
-                ));
+                PipeEvent::Message(message) => {
+                    log(
+                        Level::Error,
+                        format!("Unknown message from internal client: {}", message),
+                    );
+                }
+                PipeEvent::Open(headers, stream) => {
+                    let id = stream.id;
+                    let mut queue = client_http.lock().await;
+                    queue.feed(&id, (headers, stream));
+                }
             }
         })
         .await;
-
-        app_config
-            .logger
-            .info(format!("Internal client for token({}) disconnected", token));
-    } else {
-        app_config.logger.err(format!(
-            "Not found token({}) in current connecting peers",
-            token
-        ));
-    }
-
-    Ok(())
+    log(Level::Info, "Internal client disconnected".to_string());
 }
@@ -1,30 +1,57 @@
 use super::encode_value;
 use super::internal_decompress;
-use super::on_authenticate;
-use crate::common::websocket_peer::{Client, WebSocketPeer};
+use super::on_authenticate::{self, SessionEnd};
+use super::on_client;
+use super::session::{ParkedSession, ParkedSessions, SessionState};
+use crate::common::audit::Auditor;
+use crate::common::logger::{session_id, Level};
+use crate::common::pipe::Pipe;
+use crate::common::shutdown::Shutdown;
+use crate::common::websocket_peer::{
+    Client, ClientConnection, ClientHttp, SessionClock, WebSocketPeer,
+};
 use crate::common::AppContext;
 use futures::channel::{mpsc, oneshot};
 use futures::lock::Mutex;
-use futures::{SinkExt, StreamExt};
+use futures::{Future, SinkExt, StreamExt};
 use hyper::{upgrade::Upgraded, Request};
+use russh::client::{Handle, Prompt};
+use russh_keys::{agent::client::AgentClient, decode_secret_key};
 use serde_json::json;
-use std::{error::Error, net::SocketAddr, sync::Arc};
+use std::{error::Error, net::SocketAddr, sync::Arc, time::Instant};
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use zeroize::Zeroizing;
+
+// session state, token, internal client connection, whether file system is available, session clock and whether resumed
+type SignedIn = (
+    Arc<SessionState>,
+    String,
+    Arc<Mutex<ClientConnection>>,
+    bool,
+    Arc<SessionClock>,
+    bool,
+);
 
 pub async fn handle_request(
     context: AppContext,
     addr: &SocketAddr,
+    client_cert: &Option<String>,
     _: Request<hyper::body::Incoming>,
     mut ws_stream: WebSocketStream<Upgraded>,
 ) -> Result<(), Box<dyn Error>> {
     let app_config = &context.app_config;
     let peer_map = &context.websocket_peers;
     let authenticate_queues = &context.authenticate_queues;
-    let suspended_clients = &context.suspended_clients;
+    let login_limiter = &context.login_limiter;
+    let parked_sessions = &context.parked_sessions;
+    let shutdown = &context.shutdown;
 
+    let connected_at = Instant::now();
     app_config
         .logger
-        .info(format!("New websocket connection({}) connected", addr));
+        .event(Level::Info, "New websocket connection")
+        .field("peer", addr)
+        .emit();
 
     let config = russh::client::Config::default();
     let config = Arc::new(config);
@@ -44,25 +71,81 @@ pub async fn handle_request(
             }
             _ => continue,
         };
+        // sign in message may carry password or private key
+        let text = Zeroizing::new(text);
 
         let mut cause_cache = String::new();
+        let mut attempt_username = None;
+        let mut claimed = None;
+        let mut complete_authenticate = None;
+        // time of the failure recorded before keyboard interactive prompts
+        let mut provisional_failure = None;
         let result = async {
-            use tokio::time::{sleep, timeout, Duration};
+            use tokio::time::{timeout, Duration};
+
+            if let Some((token, secret)) = parse_resume(&text) {
+                return resume_session(&context, addr, client_cert, &token, &secret).await;
+            }
 
-            let (username, password) = match parse_username_and_password(text) {
+            let (username, credential, target) = match parse_sign_in(&text) {
                 Some(v) => v,
                 None => return Err("Sign in message format error"),
             };
+            claimed = Some((
+                username.clone(),
+                target
+                    .clone()
+                    .unwrap_or_else(|| format!("localhost:{}", app_config.local_ssh_port)),
+            ));
+
+            if !app_config.is_client_cert_allowed(client_cert, &username) {
+                cause_cache = match client_cert {
+                    Some(name) => format!(
+                        "Client certificate ({}) doesn't allow to sign in as {}",
+                        name, username
+                    ),
+                    None => "Client certificate required".to_string(),
+                };
+                return Err(cause_cache.as_str());
+            }
 
-            let sh = Client {};
+            if let Err(wait) = login_limiter.lock().await.check(&username, addr.ip()) {
+                cause_cache = format!(
+                    "Too many failed attempts, retry after {} second(s)",
+                    wait.as_secs() + 1
+                );
+                return Err(cause_cache.as_str());
+            }
+            // from now on, any failure counts
+            attempt_username = Some(username.clone());
+
+            let (host, port) = match target {
+                None => ("localhost".to_string(), app_config.local_ssh_port.clone()),
+                Some(target) => match parse_target(&target) {
+                    Some((host, port)) if app_config.is_host_allowed(&host, &port) => (host, port),
+                    Some(_) => {
+                        cause_cache = format!("Host ({}) not allowed", target);
+                        return Err(cause_cache.as_str());
+                    }
+                    None => return Err("Host format error"),
+                },
+            };
+            let sh = Client::new(app_config.clone(), host.clone(), port.clone());
             let mut session = match russh::client::connect(
                 config.clone(),
-                format!("localhost:{}", app_config.local_ssh_port),
+                format!("{}:{}", host, port),
                 sh,
             )
             .await
             {
                 Ok(session) => session,
+                Err(russh::Error::UnknownKey) => {
+                    cause_cache = format!(
+                        "Host key verification failed for {}:{}, the ssh server may be impersonated",
+                        host, port
+                    );
+                    return Err(cause_cache.as_str());
+                }
                 Err(err) => {
                     cause_cache = format!("Internal error: {:?}", err);
                     return Err(cause_cache.as_str());
@@ -70,11 +153,14 @@ pub async fn handle_request(
             };
 
             // limit one user authentication at the same time
-            let (complete_authenticate, rx) = oneshot::channel();
+            let queue_key = format!("{}@{}:{}", username, host, port);
+            let (complete, rx) = oneshot::channel();
+            // keep the queue until the attempt is recorded by login limiter
+            complete_authenticate = Some(complete);
             let authenticate_queue = {
                 let mut lock = authenticate_queues.lock().await;
                 let authenticate_queue = {
-                    match lock.get(&username) {
+                    match lock.get(&queue_key) {
                         Some(sender) => sender.upgrade(),
                         None => None,
                     }
@@ -85,7 +171,7 @@ pub async fn handle_request(
                         let (t, mut r) = mpsc::channel(0);
                         let t = Arc::new(Mutex::new(t));
                         let authenticate_queues = authenticate_queues.clone();
-                        let name = username.clone();
+                        let name = queue_key.clone();
                         tokio::spawn(async move {
                             while let Some(rx) = r.next().await {
                                 let _ = rx.await;
@@ -97,25 +183,115 @@ pub async fn handle_request(
                                 }
                             }
                         });
-                        lock.insert(username.clone(), Arc::downgrade(&t));
+                        lock.insert(queue_key.clone(), Arc::downgrade(&t));
                         t
                     }
                 }
             };
             let _ = authenticate_queue.lock().await.send(rx).await;
-            match session.authenticate_password(username, password).await {
+            // other attempts may fail while waiting in queue
+            if let Err(wait) = login_limiter.lock().await.check(&username, addr.ip()) {
+                cause_cache = format!(
+                    "Too many failed attempts, retry after {} second(s)",
+                    wait.as_secs() + 1
+                );
+                attempt_username = None;
+                return Err(cause_cache.as_str());
+            }
+            let mut password = None;
+            let interactive = matches!(
+                credential,
+                Credential::Password(_) | Credential::KeyboardInteractive
+            );
+            let authenticated = match credential {
+                Credential::Password(p) => {
+                    let authenticated = session
+                        .authenticate_password(username.as_str(), p.as_str())
+                        .await;
+                    password = Some(p);
+                    authenticated.map_err(|err| format!("Internal error: {:?}", err))
+                }
+                Credential::PrivateKey { key, passphrase } => {
+                    let passphrase = passphrase.as_ref().map(|p| p.as_str());
+                    match decode_secret_key(key.as_str(), passphrase) {
+                        Ok(key_pair) => {
+                            let key_pair = Arc::new(key_pair);
+                            let authenticated = session
+                                .authenticate_publickey(username.as_str(), key_pair.clone())
+                                .await;
+                            // russh lets go of its reference once auth finishes, the decoded key
+                            // is wiped when dropped (ed25519 zeroizes itself, openssl clears rsa)
+                            drop(key_pair);
+                            authenticated.map_err(|err| format!("Internal error: {:?}", err))
+                        }
+                        Err(err) => Err(format!("Private key decode failed: {}", err)),
+                    }
+                }
+                // identities of the agent belong to this server rather than the browser,
+                // only offered for the username the client certificate is mapped to
+                Credential::Agent => match (
+                    app_config.ssh_agent,
+                    app_config.is_client_cert_bound(client_cert, &username),
+                ) {
+                    (true, true) => authenticate_agent(&mut session, username.as_str()).await,
+                    (true, false) => Err(format!(
+                        "Ssh agent sign in requires a client certificate mapped to {}",
+                        username
+                    )),
+                    (false, _) => Err("Ssh agent sign in is disabled".to_string()),
+                },
+                Credential::KeyboardInteractive => Ok(false),
+            };
+            // server may still ask for more (second factor for example) with keyboard interactive,
+            // keys and agent are not followed by questions that only a password user can answer
+            let authenticated = match authenticated {
+                Ok(false) if interactive => {
+                    // the browser may take long to answer, let next attempt in meanwhile,
+                    // this one counts as failed until it succeeds
+                    let before_prompt = || {
+                        let complete = complete_authenticate.take();
+                        let at = Instant::now();
+                        provisional_failure = Some(at);
+                        let (context, username) = (&context, &username);
+                        async move {
+                            record_failure(context, username, addr, at).await;
+                            if let Some(complete) = complete {
+                                let _ = complete.send(());
+                            }
+                        }
+                    };
+                    authenticate_keyboard_interactive(
+                        &mut session,
+                        &mut ws_stream,
+                        username.as_str(),
+                        password,
+                        before_prompt,
+                    )
+                    .await
+                }
+                other => other,
+            };
+            match authenticated {
+                Ok(true) => {
+                    // later failures are not caused by credential
+                    let mut login_limiter = login_limiter.lock().await;
+                    login_limiter.succeed(&username);
+                    if let Some(at) = provisional_failure.take() {
+                        login_limiter.retract(addr.ip(), at);
+                    }
+                    attempt_username = None;
+                }
                 Ok(false) => {
-                    sleep(Duration::from_secs(5)).await;
-                    tokio::task::yield_now().await;
-                    return Err("Username and password authenticate failed");
+                    return Err("Username and credential authenticate failed");
                 }
                 Err(err) => {
-                    cause_cache = format!("Internal error: {:?}", err);
+                    cause_cache = err;
                     return Err(cause_cache.as_str());
                 }
-                _ => {}
             };
-            let _ = complete_authenticate.send(());
+            if let Some(complete) = complete_authenticate.take() {
+                let _ = complete.send(());
+            }
 
             let mut channel = match session.channel_open_session().await {
                 Ok(ch) => ch,
@@ -127,53 +303,102 @@ pub async fn handle_request(
 
             let token = uuid::Uuid::new_v4().to_string();
 
-            let (mut map, mut clients) = futures::join!(peer_map.lock(), suspended_clients.lock());
-            match (map.get(&token), clients.get(&token)) {
-                (Some(_), _) | (_, Some(_)) => return Err("Internal error: id generation failed"),
-                _ => (),
-            }
+            // master speaks to internal client over stdin and stdout of the exec channel,
+            // it works wherever the ssh server is, no connection back to master is needed
+            let command = format!("{} --client", app_config.bin);
+            let client_pipe = timeout(Duration::from_secs(5), async {
+                channel.exec(true, command).await.ok()?;
+                let (reader, writer) = tokio::io::split(channel.into_stream());
+                Pipe::connect(reader, writer).await.ok()
+            });
 
+            // the bin may not exist on target host,
+            // then the file system features are unavailable but shells still work
+            let (client_pipe, client_events) = match client_pipe.await {
+                Ok(Some((pipe, events))) => (Some(pipe), Some(events)),
+                _ => {
+                    app_config
+                        .logger
+                        .event(
+                            Level::Error,
+                            format!("Internal client unavailable on {}:{}", host, port),
+                        )
+                        .field("session", session_id(&token))
+                        .emit();
+                    (None, None)
+                }
+            };
+            let file_system = client_pipe.is_some();
             let (event_channel_write_channel, event_channel_read_channel) = mpsc::channel(0);
-            let (client_write_channel_callback, rx) = oneshot::channel();
-            clients.insert(
-                token.clone(),
-                (client_write_channel_callback, event_channel_write_channel),
+            let client_connection = Arc::new(Mutex::new(ClientConnection::new(
+                app_config.clone(),
+                event_channel_write_channel,
+                client_pipe,
+            )));
+            let client_http = Arc::new(Mutex::new(ClientHttp::new()));
+            if let Some(events) = client_events {
+                tokio::spawn(on_client::handle_request(
+                    app_config.clone(),
+                    token.clone(),
+                    client_connection.clone(),
+                    client_http.clone(),
+                    events,
+                ));
+            }
+            let clock = Arc::new(SessionClock::new(
+                app_config.session_lifetime,
+                app_config.session_idle_timeout,
+            ));
+            let auditor = Auditor::new(
+                &app_config.audit_log,
+                &token,
+                &username,
+                &format!("{}:{}", host, port),
             );
-            drop(clients);
-
-            let command = format!(
-                "{} --client {} --listen-address localhost:{}",
-                app_config.bin,
-                token,
-                app_config.listen_address.port()
+            let peer = WebSocketPeer::new(
+                client_connection.clone(),
+                client_http,
+                clock.clone(),
+                auditor.clone(),
             );
-            tokio::spawn(async move { channel.exec(true, command).await });
-
-            let client_connection = match timeout(Duration::from_secs(5), rx).await {
-                Ok(Ok(client_write_channel)) => client_write_channel,
-                v => {
-                    if let Err(_) = v {
-                        // error cause by timeout
-                        suspended_clients.lock().await.remove(&token);
-                    }
-                    return Err("Failed to connect to client");
-                }
-            };
-            let peer = WebSocketPeer::new(client_connection.clone());
+            let mut map = peer_map.lock().await;
+            if map.contains_key(&token) {
+                peer.disconnect().await;
+                return Err("Internal error: id generation failed");
+            }
             map.insert(token.clone(), peer);
-            return Ok((
+            let state = Arc::new(SessionState::new(
                 session,
-                token,
-                client_connection,
                 event_channel_read_channel,
+                auditor,
             ));
+            Ok((state, token, client_connection, file_system, clock, false))
         }
         .await;
 
         match result {
             Err(cause) => {
                 let message = format!("Authenticate failed ({})", cause);
-                app_config.logger.err(format!("{} for {:?}", message, addr));
+                let mut event = app_config
+                    .logger
+                    .event(Level::Error, &message)
+                    .field("peer", addr);
+                if let Some(username) = &attempt_username {
+                    event = event.field("username", username);
+                }
+                event.emit();
+                let mut record =
+                    json!({"result": "failure", "peer": addr.to_string(), "reason": cause});
+                if let Some((username, target)) = &claimed {
+                    record["username"] = json!(username);
+                    record["target"] = json!(target);
+                    app_config.metrics.login(false);
+                }
+                app_config.audit_log.record("login", record);
+                if let Some(username) = attempt_username.filter(|_| provisional_failure.is_none()) {
+                    record_failure(&context, &username, addr, Instant::now()).await;
+                }
+                drop(complete_authenticate);
                 let response = json!({"error": message});
                 let msg = encode_value(response);
                 ws_stream.send(msg).await?;
@@ -185,35 +410,485 @@ pub async fn handle_request(
         }
     }
 
-    if let Some((session, token, client_connection, rx)) = token_and_connection {
-        let response = json!({ "token": token });
+    let signed_in = token_and_connection
+        .as_ref()
+        .map(|(state, token, ..)| (session_id(token), state.auditor.username().to_string()));
+    if let Some((state, token, client_connection, file_system, clock, resumed)) =
+        token_and_connection
+    {
+        // browser presents it with token to resume the session after websocket disconnected
+        let secret = uuid::Uuid::new_v4().to_string();
+        let mut response = json!({ "token": token, "fileSystem": file_system });
+        if app_config.resume_grace.is_some() {
+            response["resumeSecret"] = json!(secret);
+        }
+        if resumed {
+            response["shells"] = json!(state.shell_ids().await);
+        }
         let msg = encode_value(response);
+        let event = match resumed {
+            true => app_config.logger.event(Level::Info, "Session resumed"),
+            false => app_config.logger.event(Level::Info, "Signed in"),
+        };
+        event
+            .field("session", session_id(&token))
+            .field("peer", addr)
+            .field("username", state.auditor.username())
+            .emit();
+        let kind = match resumed {
+            true => "resume",
+            false => {
+                app_config.metrics.login(true);
+                "login"
+            }
+        };
+        state
+            .auditor
+            .record(addr, kind, json!({"result": "success"}));
+        let mut parkable = true;
         if let Ok(_) = ws_stream.send(msg).await {
-            let _ =
-                on_authenticate::handle_request(&token, &client_connection, ws_stream, rx, session)
-                    .await;
+            let result = on_authenticate::handle_request(
+                &context,
+                addr,
+                &token,
+                &client_connection,
+                ws_stream,
+                &state,
+                &clock,
+            )
+            .await;
+            match result {
+                Ok(SessionEnd::Expired(reason)) => {
+                    app_config
+                        .logger
+                        .event(Level::Info, format!("Session expired ({})", reason))
+                        .field("session", session_id(&token))
+                        .field("peer", addr)
+                        .emit();
+                    parkable = false;
+                }
+                Ok(SessionEnd::SignedOut | SessionEnd::Shutdown) => parkable = false,
+                _ => {}
+            }
         }
-        let mut map = peer_map.lock().await;
-        if let Some(peer) = map.remove(&token) {
-            peer.disconnect().await;
+        let resumed = match (parkable, app_config.resume_grace) {
+            (true, Some(grace)) => {
+                let parked = ParkedSession {
+                    secret,
+                    state: state.clone(),
+                    client_connection,
+                    clock,
+                    file_system,
+                    resumed: oneshot::channel().0,
+                };
+                park_session(parked_sessions, shutdown, &token, parked, grace).await
+            }
+            _ => false,
+        };
+        if resumed {
+            app_config
+                .logger
+                .event(
+                    Level::Info,
+                    "Session parked and resumed by other connection",
+                )
+                .field("session", session_id(&token))
+                .field("peer", addr)
+                .emit();
+        } else {
+            let mut map = peer_map.lock().await;
+            if let Some(peer) = map.remove(&token) {
+                peer.disconnect().await;
+            }
+            drop(map);
+            state.close().await;
         }
     }
 
-    app_config
+    let mut event = app_config
         .logger
-        .info(format!("The websocket connection({}) disconnected", addr));
+        .event(Level::Info, "The websocket connection disconnected")
+        .field("peer", addr)
+        .duration("duration", connected_at.elapsed());
+    if let Some((session, username)) = &signed_in {
+        event = event.field("session", session).field("username", username);
+    }
+    event.emit();
 
     Ok(())
 }
 
-fn parse_username_and_password(text: String) -> Option<(String, String)> {
-    use serde_json::Value::{Object, String};
-    if let Ok(Object(mut obj)) = serde_json::from_str::<serde_json::Value>(text.as_str()) {
-        let username = obj.remove("username");
-        let password = obj.remove("password");
-        if let (Some(String(username)), Some(String(password))) = (username, password) {
-            return Some((username, password));
+/// Wait for browser to resume the session in grace period and return whether it's resumed.
+/// The session is consumed by the resuming connection if resumed.
+async fn park_session(
+    parked_sessions: &ParkedSessions,
+    shutdown: &Shutdown,
+    token: &String,
+    mut parked: ParkedSession,
+    grace: std::time::Duration,
+) -> bool {
+    let (resumed, mut on_resumed) = oneshot::channel();
+    parked.resumed = resumed;
+    let state = parked.state.clone();
+    let clock = parked.clock.clone();
+    parked_sessions.lock().await.insert(token.clone(), parked);
+    // keep shells running and buffer their output for browser
+    let drain = async {
+        let mut events = state.events.lock().await;
+        while let Some(event) = events.next().await {
+            state.park_event(event).await;
+        }
+        futures::future::pending::<()>().await
+    };
+    tokio::select! {
+        v = &mut on_resumed => if v.is_ok() { return true; },
+        _ = tokio::time::sleep(grace) => {},
+        _ = clock.expired() => {},
+        _ = shutdown.closing.cancelled() => {},
+        _ = drain => {},
+    }
+    // resuming connection takes the session and signals under the lock,
+    // so under the lock it's either signaled or still parked
+    let mut sessions = parked_sessions.lock().await;
+    match on_resumed.try_recv() {
+        Ok(Some(())) => true,
+        _ => {
+            sessions.remove(token);
+            false
+        }
+    }
+}
+
+/// The browser that resumes goes through the same checks as a fresh sign in,
+/// the session stays parked if they refuse it.
+async fn resume_session(
+    context: &AppContext,
+    addr: &SocketAddr,
+    client_cert: &Option<String>,
+    token: &String,
+    secret: &str,
+) -> Result<SignedIn, &'static str> {
+    let app_config = &context.app_config;
+    if !app_config.sign_in_ip_filter.is_allowed(&addr.ip()) {
+        return Err("Sign in isn't allowed from this address");
+    }
+    let mut sessions = context.parked_sessions.lock().await;
+    let username = match sessions.get(token) {
+        Some(parked) if constant_time_eq(parked.secret.as_bytes(), secret.as_bytes()) => {
+            parked.state.auditor.username().to_string()
+        }
+        _ => return Err("Session can't be resumed"),
+    };
+    if !app_config.is_client_cert_allowed(client_cert, &username) {
+        return Err("Client certificate doesn't allow to resume the session");
+    }
+    if context
+        .login_limiter
+        .lock()
+        .await
+        .check(&username, addr.ip())
+        .is_err()
+    {
+        return Err("Too many failed attempts, retry later");
+    }
+    let parked = match sessions.remove(token) {
+        Some(parked) => parked,
+        None => return Err("Session can't be resumed"),
+    };
+    // signal before other connection can look at the parked sessions,
+    // the session is this connection's from now on even if the parking one is gone
+    let _ = parked.resumed.send(());
+    drop(sessions);
+    Ok((
+        parked.state,
+        token.clone(),
+        parked.client_connection,
+        parked.file_system,
+        parked.clock,
+        true,
+    ))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn authenticate_agent(session: &mut Handle<Client>, username: &str) -> Result<bool, String> {
+    let mut agent = AgentClient::connect_env()
+        .await
+        .map_err(|err| format!("Ssh agent not available: {}", err))?;
+    let identities = agent
+        .request_identities()
+        .await
+        .map_err(|err| format!("Ssh agent request identities failed: {}", err))?;
+    for key in identities {
+        let (a, result) = session.authenticate_future(username, key, agent).await;
+        agent = a;
+        if let Ok(true) = result {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+async fn record_failure(context: &AppContext, username: &str, addr: &SocketAddr, at: Instant) {
+    let banned = context
+        .login_limiter
+        .lock()
+        .await
+        .fail(username, addr.ip(), at);
+    for (key, duration) in banned {
+        context.app_config.logger.err(format!(
+            "Ban {} for {:?} after too many failed sign in attempts (last from {})",
+            key, duration, addr
+        ));
+    }
+}
+
+/// `before_prompt` runs once, before the first question is sent to the browser.
+async fn authenticate_keyboard_interactive<F, Fut>(
+    session: &mut Handle<Client>,
+    ws_stream: &mut WebSocketStream<Upgraded>,
+    username: &str,
+    mut password: Option<Zeroizing<String>>,
+    before_prompt: F,
+) -> Result<bool, String>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    use russh::client::KeyboardInteractiveAuthResponse::{Failure, InfoRequest, Success};
+    let mut before_prompt = Some(before_prompt);
+    let deadline = tokio::time::Instant::now() + PROMPT_TIMEOUT;
+    let mut response = session
+        .authenticate_keyboard_interactive_start(username, None)
+        .await;
+    for _ in 0..MAX_PROMPT_ROUNDS {
+        match response {
+            Ok(Success) => return Ok(true),
+            Ok(Failure) => return Ok(false),
+            Ok(InfoRequest {
+                name,
+                instructions,
+                prompts,
+            }) => {
+                let responses = if prompts.is_empty() {
+                    vec![]
+                } else {
+                    // most pam stacks ask the password first, answer it with the one already provided
+                    match password.take() {
+                        Some(p) if is_password_prompt(&prompts) => vec![p.to_string()],
+                        p => {
+                            password = p;
+                            if let Some(before_prompt) = before_prompt.take() {
+                                before_prompt().await;
+                            }
+                            ask_prompts(ws_stream, deadline, name, instructions, prompts).await?
+                        }
+                    }
+                };
+                response = session
+                    .authenticate_keyboard_interactive_respond(responses)
+                    .await;
+            }
+            Err(err) => return Err(format!("Internal error: {:?}", err)),
+        }
+    }
+    Err("Too many keyboard interactive rounds".to_string())
+}
+
+fn is_password_prompt(prompts: &[Prompt]) -> bool {
+    match prompts {
+        [prompt] => !prompt.echo && prompt.prompt.to_lowercase().contains("password"),
+        _ => false,
+    }
+}
+
+async fn ask_prompts(
+    ws_stream: &mut WebSocketStream<Upgraded>,
+    deadline: tokio::time::Instant,
+    name: String,
+    instructions: String,
+    prompts: Vec<Prompt>,
+) -> Result<Vec<String>, String> {
+    use tokio::time::timeout_at;
+    let count = prompts.len();
+    let prompts: Vec<_> = prompts
+        .into_iter()
+        .map(|p| json!({"prompt": p.prompt, "echo": p.echo}))
+        .collect();
+    let msg = encode_value(json!({
+        "prompt": {"name": name, "instructions": instructions, "prompts": prompts}
+    }));
+    ws_stream
+        .send(msg)
+        .await
+        .map_err(|err| format!("Websocket error: {:?}", err))?;
+    loop {
+        let data = match timeout_at(deadline, ws_stream.next()).await {
+            Ok(Some(Ok(data))) => data,
+            Ok(_) => return Err("Connection closed while waiting for prompt answer".to_string()),
+            Err(_) => return Err("Prompt answer timeout".to_string()),
+        };
+        let text = match data {
+            Message::Text(t) => t,
+            Message::Binary(bytes) => match internal_decompress(&bytes[..]) {
+                Ok(t) => t,
+                Err(_) => continue,
+            },
+            Message::Ping(bytes) => {
+                let _ = ws_stream.send(Message::Pong(bytes)).await;
+                continue;
+            }
+            _ => continue,
+        };
+        let text = Zeroizing::new(text);
+        return match parse_prompt_responses(&text) {
+            Some(responses) if responses.len() == count => Ok(responses),
+            _ => Err("Prompt answer format error".to_string()),
+        };
+    }
+}
+
+// all questions of one sign in are to be answered in time, the pam stack decides how many there are
+const PROMPT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+const MAX_PROMPT_ROUNDS: usize = 8;
+
+fn parse_prompt_responses(text: &str) -> Option<Vec<String>> {
+    use serde_json::Value::{Array, Object, String};
+    if let Ok(Object(mut obj)) = serde_json::from_str::<serde_json::Value>(text) {
+        if let Some(Array(responses)) = obj.remove("responses") {
+            let mut v = Vec::with_capacity(responses.len());
+            for response in responses {
+                match response {
+                    String(response) => v.push(response),
+                    _ => return None,
+                }
+            }
+            return Some(v);
+        }
+    }
+    None
+}
+
+enum Credential {
+    Password(Zeroizing<String>),
+    PrivateKey {
+        key: Zeroizing<String>,
+        passphrase: Option<Zeroizing<String>>,
+    },
+    Agent,
+    KeyboardInteractive,
+}
+
+fn parse_sign_in(text: &str) -> Option<(String, Credential, Option<String>)> {
+    use serde_json::Value::{Bool, Object, String};
+    if let Ok(Object(mut obj)) = serde_json::from_str::<serde_json::Value>(text) {
+        let username = match obj.remove("username") {
+            Some(String(username)) => username,
+            _ => return None,
+        };
+        let target = match obj.remove("host") {
+            Some(String(host)) if !host.is_empty() => Some(host),
+            _ => None,
+        };
+        if let Some(Bool(true)) = obj.remove("agent") {
+            return Some((username, Credential::Agent, target));
+        }
+        if let Some(Bool(true)) = obj.remove("keyboardInteractive") {
+            return Some((username, Credential::KeyboardInteractive, target));
+        }
+        if let Some(String(key)) = obj.remove("privateKey") {
+            let passphrase = match obj.remove("passphrase") {
+                Some(String(passphrase)) if !passphrase.is_empty() => {
+                    Some(Zeroizing::new(passphrase))
+                }
+                _ => None,
+            };
+            let key = Zeroizing::new(key);
+            let credential = Credential::PrivateKey { key, passphrase };
+            return Some((username, credential, target));
+        }
+        if let Some(String(password)) = obj.remove("password") {
+            let credential = Credential::Password(Zeroizing::new(password));
+            return Some((username, credential, target));
         }
     }
     return None;
 }
+
+fn parse_resume(text: &str) -> Option<(String, String)> {
+    use serde_json::Value::{Object, String};
+    if let Ok(Object(mut obj)) = serde_json::from_str::<serde_json::Value>(text) {
+        if let Some(Object(mut resume)) = obj.remove("resume") {
+            if let (Some(String(token)), Some(String(secret))) =
+                (resume.remove("token"), resume.remove("secret"))
+            {
+                return Some((token, secret));
+            }
+        }
+    }
+    None
+}
+
+/// Split `host`, `host:port` or `[ipv6]:port` into host and port (default: 22).
+fn parse_target(target: &str) -> Option<(String, String)> {
+    let (host, port) = match target.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, "22"),
+            Some((host, port)) => (host, port.strip_prefix(':')?),
+            None => return None,
+        },
+        None => match target.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, port),
+            Some(_) => (target, "22"), // bare ipv6 address
+            None => (target, "22"),
+        },
+    };
+    match (host.is_empty(), port.parse::<u16>()) {
+        (false, Ok(_)) => Some((host.to_string(), port.to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(host: &str, port: &str) -> Option<(String, String)> {
+        Some((host.to_string(), port.to_string()))
+    }
+
+    #[test]
+    fn host_and_port() {
+        assert_eq!(parse_target("example.com"), target("example.com", "22"));
+        assert_eq!(
+            parse_target("example.com:2222"),
+            target("example.com", "2222")
+        );
+        assert_eq!(parse_target("10.0.0.7:22"), target("10.0.0.7", "22"));
+    }
+
+    #[test]
+    fn ipv6() {
+        assert_eq!(
+            parse_target("[2001:db8::1]:2222"),
+            target("2001:db8::1", "2222")
+        );
+        assert_eq!(parse_target("[2001:db8::1]"), target("2001:db8::1", "22"));
+        assert_eq!(parse_target("2001:db8::1"), target("2001:db8::1", "22"));
+        assert_eq!(parse_target("::1"), target("::1", "22"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse_target(""), None);
+        assert_eq!(parse_target(":22"), None);
+        assert_eq!(parse_target("[]:22"), None);
+        assert_eq!(parse_target("example.com:"), None);
+        assert_eq!(parse_target("example.com:ssh"), None);
+        assert_eq!(parse_target("example.com:65536"), None);
+        assert_eq!(parse_target("[2001:db8::1"), None);
+        assert_eq!(parse_target("[2001:db8::1]2222"), None);
+    }
+}
@@ -0,0 +1,113 @@
+use super::shell::PollChannelData;
+use crate::common::audit::Auditor;
+use crate::common::websocket_peer::{Client, ClientConnection, SessionClock};
+use futures::channel::{mpsc, oneshot};
+use futures::lock::Mutex;
+use russh::client::Handle;
+use serde_json::json;
+use std::{collections::HashMap, sync::Arc};
+
+// shell output kept for each shell while browser is away, older output is dropped
+const BACKLOG_LIMIT: usize = 256 * 1024;
+
+/// Everything that a signed in browser works with and outlives one websocket connection.
+pub struct SessionState {
+    pub session: Mutex<Handle<Client>>,
+    pub shells: Mutex<HashMap<String, mpsc::Sender<PollChannelData>>>,
+    pub events: Mutex<mpsc::Receiver<serde_json::Value>>,
+    pub auditor: Auditor,
+    backlog: Mutex<HashMap<String, Backlog>>, // shell output that browser hasn't received
+}
+
+#[derive(Default)]
+struct Backlog {
+    data: Vec<u8>,
+    closed: bool,
+}
+
+impl SessionState {
+    pub fn new(
+        session: Handle<Client>,
+        events: mpsc::Receiver<serde_json::Value>,
+        auditor: Auditor,
+    ) -> Self {
+        Self {
+            session: Mutex::new(session),
+            shells: Mutex::new(HashMap::new()),
+            events: Mutex::new(events),
+            auditor,
+            backlog: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep the event that can't be delivered to browser.
+    /// Only shell output is kept and other events (like watch) are dropped.
+    pub async fn park_event(&self, event: serde_json::Value) {
+        let shell = match event.get("shell") {
+            Some(shell) => shell,
+            None => return,
+        };
+        let id = match shell.get("id").and_then(|id| id.as_str()) {
+            Some(id) => id.to_string(),
+            None => return,
+        };
+        let mut backlog = self.backlog.lock().await;
+        let backlog = backlog.entry(id).or_default();
+        if shell.get("close").is_some() {
+            // shell exited while browser is away and it can't be resumed
+            backlog.closed = true;
+        } else if let Some(serde_json::Value::Array(data)) = shell.get("data") {
+            let buffer = &mut backlog.data;
+            buffer.extend(data.iter().filter_map(|v| v.as_u64()).map(|v| v as u8));
+            if buffer.len() > BACKLOG_LIMIT {
+                let overflow = buffer.len() - BACKLOG_LIMIT;
+                buffer.drain(..overflow);
+            }
+        }
+    }
+
+    /// Take the shell output that produced while browser is away and whether the shell exited.
+    pub async fn take_backlog(&self, id: &str) -> (Option<serde_json::Value>, bool) {
+        match self.backlog.lock().await.remove(id) {
+            Some(Backlog { data, closed }) => {
+                let event = match data.is_empty() {
+                    true => None,
+                    false => Some(json!({"shell": {"id": id, "data": data}})),
+                };
+                (event, closed)
+            }
+            None => (None, false),
+        }
+    }
+
+    /// Close the ssh connection and the shells with it.
+    pub async fn close(&self) {
+        let session = self.session.lock().await;
+        let _ = session
+            .disconnect(russh::Disconnect::ByApplication, "", "English")
+            .await;
+    }
+
+    /// Shells that are still alive.
+    pub async fn shell_ids(&self) -> Vec<String> {
+        let shells = self.shells.lock().await;
+        let backlog = self.backlog.lock().await;
+        shells
+            .keys()
+            .filter(|id| !backlog.get(*id).map(|b| b.closed).unwrap_or(false))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Session whose websocket disconnected and waits for browser to resume it in grace period.
+pub struct ParkedSession {
+    pub secret: String,
+    pub state: Arc<SessionState>,
+    pub client_connection: Arc<Mutex<ClientConnection>>,
+    pub clock: Arc<SessionClock>,
+    pub file_system: bool,
+    pub resumed: oneshot::Sender<()>,
+}
+
+pub type ParkedSessions = Arc<Mutex<HashMap<String, ParkedSession>>>;
@@ -1,23 +1,38 @@
 use futures::channel::mpsc;
 use futures::SinkExt;
 use futures::{lock::Mutex, StreamExt};
-use russh::{client::Handle, client::Msg, Channel, ChannelMsg};
-use std::{collections::HashMap, sync::Arc};
+use russh::{client::Msg, Channel, ChannelMsg};
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
 
-use crate::common::websocket_peer::{Client, ClientWebsocket};
+use super::session::SessionState;
+use crate::common::app_config::AppConfig;
+use crate::common::websocket_peer::ClientConnection;
 
 pub async fn handle_request(
+    app_config: &Arc<AppConfig>,
+    addr: &SocketAddr,
     request: serde_json::Value,
-    client_connection: &Arc<Mutex<ClientWebsocket>>,
-    session: &Mutex<Handle<Client>>,
-    shells: &Mutex<HashMap<String, mpsc::Sender<PollChannelData>>>,
+    client_connection: &Arc<Mutex<ClientConnection>>,
+    state: &SessionState,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut shells = shells.lock().await;
+    let mut shells = state.shells.lock().await;
 
     match request {
         serde_json::Value::String(id) => {
-            if let None = shells.get(&id) {
-                let session = session.lock().await;
+            // browser resumed the session and reopens the shell, replay what it missed
+            let (backlog, closed) = state.take_backlog(&id).await;
+            if closed {
+                shells.remove(&id);
+            }
+            if let Some(event) = backlog {
+                let mut conn = client_connection.lock().await;
+                conn.forward_event(event)
+                    .await
+                    .map_err(|_| "Event channel closed")?;
+            }
+            if !shells.contains_key(&id) {
+                let session = state.session.lock().await;
                 let mut channel = session.channel_open_session().await?;
                 drop(session);
                 channel
@@ -27,7 +42,18 @@ pub async fn handle_request(
                 let (tx, rx) = mpsc::channel(0);
                 shells.insert(id.clone(), tx);
                 drop(shells);
-                tokio::spawn(poll_channel(channel, rx, client_connection.clone(), id));
+                let auditor = state.auditor.clone();
+                let addr = *addr;
+                auditor.record(&addr, "shell_open", json!({ "shell": id }));
+                app_config.metrics.shell_opened();
+                let app_config = app_config.clone();
+                let client_connection = client_connection.clone();
+                tokio::spawn(async move {
+                    let result = poll_channel(channel, rx, client_connection, id.clone()).await;
+                    auditor.record(&addr, "shell_close", json!({ "shell": id }));
+                    app_config.metrics.shell_closed();
+                    result
+                });
             }
         }
         serde_json::Value::Object(mut request) => {
@@ -126,10 +152,9 @@ impl PollChannelData {
 async fn poll_channel(
     mut channel: Channel<Msg>,
     mut rx: mpsc::Receiver<PollChannelData>,
-    client_connection: Arc<Mutex<ClientWebsocket>>,
+    client_connection: Arc<Mutex<ClientConnection>>,
     id: String,
 ) -> Result<(), russh::Error> {
-    use serde_json::json;
     loop {
         tokio::select! {
             data = rx.next() => {
@@ -28,13 +28,15 @@ function App() {
 
 export default App;
 
-const { host } = document.location;
+const { host, pathname } = document.location;
+// page is served at the base path with trailing slash, e.g. '/' or '/ssh/'
+const basePath = pathname.replace(/[^/]*$/, '');
 
 // const isDebug = process.env.NODE_ENV !== 'production'; // do not export this variable
 // const _ = process.env.NODE_ENV !== 'production' ? fetch(`https://${hostname}:7200/`, { mode: 'cors' }).catch(error => { }) : {};
 // const wsUri = import.meta.env.DEV ? `wss://${hostname}:7200/rest` : `wss://${host}/rest`
 
-const wsUri = `wss://${host}/rest`;
+const wsUri = `wss://${host}${basePath}rest`;
 
 class AppServer implements Server.Type {
   static _id = 0;
@@ -47,23 +49,39 @@ class AppServer implements Server.Type {
   readonly id: number;
   readonly ws: WebSocket;
 
-  async signIn(props: { username: string, password: string }): Promise<{ token: string; } | { error: Error; }> {
-    const config = { ...props };
+  async signIn(props: Server.SignInProps, onPrompt?: Server.OnPrompt): Promise<Server.SignInResult | { error: Error; }> {
+    return this._send({ ...props }, onPrompt);
+  }
+
+  async resume(props: { token: string, secret: string }): Promise<Server.SignInResult | { error: Error; }> {
+    return this._send({ resume: props });
+  }
+
+  protected async _send(config: object, onPrompt?: Server.OnPrompt): Promise<Server.SignInResult | { error: Error; }> {
     const arr = await encodeMessage(config);
     return new Promise((resolve, reject) => {
       const ws = this.ws;
-      ws.addEventListener('message',
-        async ({ data }) => {
-          const obj = await decodeMessage(data);
-          if (obj === undefined) reject(obj);
-          resolve(obj)
-        }, { once: true });
+      const onMessage = async ({ data }: MessageEvent) => {
+        const obj = await decodeMessage(data);
+        if (obj !== undefined && 'prompt' in obj) {
+          // keyboard interactive authentication (second factor for example)
+          const prompt = obj.prompt as Server.Prompt;
+          const answers = await onPrompt?.(prompt);
+          // empty answers let server fail the attempt at once rather than wait
+          const responses = answers ?? prompt.prompts.map(() => "");
+          ws.send(await encodeMessage({ responses }));
+          return;
+        }
+        ws.removeEventListener('message', onMessage);
+        if (obj === undefined) reject(obj);
+        resolve(obj)
+      };
+      ws.addEventListener('message', onMessage);
       ws.send(arr);
     });
   }
 }
 
-
 class Service extends React.Component<Service.Props, Service.State> {
   constructor(props: Service.Props) {
     super(props);
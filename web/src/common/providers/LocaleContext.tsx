@@ -10,6 +10,10 @@ const english = {
   help: 'Help',
   username: 'Username',
   password: 'Password',
+  usePrivateKey: 'Use Private Key',
+  privateKey: 'Private Key',
+  openKeyFile: 'Open key file',
+  passphrase: 'Passphrase (optional)',
   ok: 'OK',
   cancel: 'Cancel',
   rememberPassword: 'Remember Password',
@@ -5,8 +5,17 @@ export namespace Server {
   export interface Type {
     readonly ws: WebSocket;
     readonly id: number;
-    signIn: (props: { username: string, password: string }) => Promise<{ token: string } | { error: Error }>;
+    signIn: (props: SignInProps, onPrompt?: OnPrompt) => Promise<SignInResult | { error: Error }>;
+    resume: (props: { token: string, secret: string }) => Promise<SignInResult | { error: Error }>;
   }
+  // private key (OpenSSH or PEM text) is used instead of password if given
+  export type SignInProps = { username: string, password?: string, privateKey?: string, passphrase?: string };
+  // keyboard interactive questions of the ssh server (second factor for example), echo false means secret
+  export type Prompt = { name: string, instructions: string, prompts: { prompt: string, echo: boolean }[] };
+  // resolves with one answer for each prompt, or undefined if the user gives up
+  export type OnPrompt = (prompt: Prompt) => Promise<string[] | undefined>;
+  // shells are the alive shells of the resumed session
+  export type SignInResult = { token: string, fileSystem?: boolean, resumeSecret?: string, shells?: string[] };
   export const Context = React.createContext<Type>(undefined as unknown as Type);
 
   export namespace Authentication {
@@ -19,6 +28,7 @@ export namespace Server {
       readonly shell: EventTarget;
       readonly watch: EventTarget;
       readonly notification: EventTarget;
+      readonly resumedShells: string[];
       signOut(): void;
       upload(data: File, dest: Rest.PathLike, filename: string | null, init?: {
         signal?: AbortSignal | null
@@ -1,6 +1,6 @@
 import '../components/Layout.css';
 import React from "react";
-import { Card, TextField, Checkbox, Typography, LinearProgress, Button, FormField, IconButton, Icon, Tooltip } from 'rmcw';
+import { Card, TextField, TextArea, Checkbox, Typography, LinearProgress, Button, FormField, IconButton, Icon, Tooltip, Dialog } from 'rmcw';
 import lazy from 'react-lazy-with-preload';
 
 import { SharedAxis, SharedAxisTransform } from 'material-design-transform';
@@ -9,6 +9,7 @@ import { Rest } from '../common/Type';
 import { wsSafeClose } from '../common/DomTools';
 import Scaffold from '../components/Scaffold';
 import LayoutBuilder from '../components/LayoutBuilder';
+import useInputAutoFocusRef from '../components/InputAutoFocusRef';
 import { decodeMessage, encodeMessage } from '../workers/Compress';
 
 // @TODO: more-security way for storage username and password
@@ -16,7 +17,15 @@ import { decodeMessage, encodeMessage } from '../workers/Compress';
 const HomePage = lazy(() => import('./HomePage'));
 HomePage.preload();
 
-const { host } = document.location;
+const { host, pathname } = document.location;
+// page is served at the base path with trailing slash, e.g. '/' or '/ssh/'
+const basePath = pathname.replace(/[^/]*$/, '');
+
+// token and resume secret of current session, that survive page reload but not leave this tab
+const SESSION_KEY = 'session';
+
+// private keys are a few KiB at most, anything bigger is not a key
+const MAX_KEY_FILE_SIZE = 64 * 1024;
 
 function SignInPage() {
   const settings = React.useContext(Settings.Context);
@@ -33,11 +42,15 @@ class Content extends React.Component<Content.Props, Content.State> {
     super(props);
     const { settings } = this.props;
     const password = settings.rememberPassword ? (settings.sshPassword ?? "") : "";
-    this.state = { visibility: null, loading: false, username: settings.sshUserName ?? "", password };
+    this.state = {
+      visibility: null, loading: false, username: settings.sshUserName ?? "", password,
+      usePrivateKey: false, privateKey: "", passphrase: "",
+    };
   }
 
   protected _usernameRef = React.createRef<HTMLLabelElement>();
   protected _passwordRef = React.createRef<HTMLLabelElement>();
+  protected _keyFileRef = React.createRef<HTMLInputElement>();
   static _focusInput(ref: React.RefObject<Element>) {
     const { current } = ref;
     if (current !== null) {
@@ -50,18 +63,29 @@ class Content extends React.Component<Content.Props, Content.State> {
     if (this.state.auth) return; // already sign in
     const { server, settings } = this.props;
     this.setState({ loading: true });
-    const { password, username } = this.state;
-    const result = await server.signIn({ username, password });
+    const { password, username, usePrivateKey, privateKey, passphrase } = this.state;
+    const result = await server.signIn(usePrivateKey
+      ? { username, privateKey, passphrase }
+      : { username, password }, this._onPrompt);
+    // server may give up waiting for the answers
+    this.state.prompt?.resolve(undefined);
+    this.setState({ prompt: undefined });
     if (Rest.isError(result)) {
       this.setState({ loading: false });
       this.props.snackbar.showMessage({ content: `${result.error}` });
       Content._focusInput(this._passwordRef);
     } else {
       settings.setSshUserName(username);
-      if (settings.rememberPassword) {
+      if (settings.rememberPassword && !usePrivateKey) {
         settings.setSshPassword(password);
       }
-      this.setState({ auth: new Auth({ server }), loading: false });
+      Content._saveSession(result);
+      // the key is never kept once signed in
+      this.setState({ auth: new Auth({ server }), loading: false, privateKey: "", passphrase: "" });
+      if (result.fileSystem === false) {
+        this.props.snackbar.showMessage({ content: "File system is unavailable on this host, only shell works" });
+      }
+      if (usePrivateKey) return;
       try {
         const credential = await navigator.credentials.create({
           id: "sign-in",
@@ -77,7 +101,34 @@ class Content extends React.Component<Content.Props, Content.State> {
     }
   }
 
-  override componentDidMount() {
+  protected readonly _onPrompt = (prompt: Server.Prompt) => new Promise<string[] | undefined>(resolve => {
+    this.setState({ prompt: { prompt, resolve } });
+  });
+
+  static _saveSession({ token, resumeSecret }: Server.SignInResult) {
+    if (resumeSecret === undefined) sessionStorage.removeItem(SESSION_KEY);
+    else sessionStorage.setItem(SESSION_KEY, JSON.stringify({ token, secret: resumeSecret }));
+  }
+
+  // resume the session that was interrupted by page reload or unstable network
+  async _resume() {
+    const session = sessionStorage.getItem(SESSION_KEY);
+    if (session === null) return false;
+    sessionStorage.removeItem(SESSION_KEY);
+    const { server } = this.props;
+    this.setState({ loading: true });
+    const result = await server.resume(JSON.parse(session));
+    if (Rest.isError(result)) {
+      this.setState({ loading: false });
+      return false;
+    }
+    Content._saveSession(result);
+    this.setState({ auth: new Auth({ server, resumedShells: result.shells }), loading: false });
+    return true;
+  }
+
+  override async componentDidMount() {
+    if (await this._resume()) return;
     const { settings } = this.props;
     if (settings.rememberPassword &&
       settings.sshUserName !== null && settings.sshPassword !== null) {
@@ -95,10 +146,17 @@ class Content extends React.Component<Content.Props, Content.State> {
 
   override render() {
     const { settings, locale: { meta } } = this.props;
-    const { auth, loading, visibility } = this.state;
+    const { auth, loading, visibility, usePrivateKey, prompt } = this.state;
     return (
       <>
         <LinearProgress closed={!loading} style={{ position: 'absolute', top: 0 }} />
+        <PromptDialog
+          prompt={prompt?.prompt}
+          locale={this.props.locale}
+          close={answers => {
+            prompt?.resolve(answers);
+            this.setState({ prompt: undefined });
+          }} />
         <SharedAxis
           className='full-size'
           keyId={auth === undefined ? 0 : 1}
@@ -146,49 +204,90 @@ class Content extends React.Component<Content.Props, Content.State> {
                     onChange={(e) => this.setState({ username: e.target.value })}
                     onFocus={e => e.target.select()}
                   />
-                  <div style={{ height: '16px' }} />
-                  <TextField ref={this._passwordRef}
-                    outlined
-                    autoComplete='current-password'
-                    id='password'
-                    type={visibility !== null ? 'text' : 'password'}
-                    name='password'
-                    style={{ width: '100%' }}
-                    label={meta.password}
-                    value={this.state.password}
-                    trailingIcon={<IconButton
-                      type='button'
-                      style={{ alignSelf: 'center', margin: '0 4px' }}
-                      onClick={e => {
-                        e.preventDefault();
-                        if (visibility) {
-                          window.clearTimeout(visibility);
-                          this.setState({ visibility: null });
-                        } else {
-                          const visibility = window.setTimeout(() => {
-                            this.setState({ visibility: null });
-                          }, 3000);
-                          this.setState({ visibility });
-                        }
-                      }}><Icon>{visibility !== null ? "visibility" : "visibility_off"}</Icon>
-                    </IconButton>}
-                    onChange={(e) => this.setState({ password: e.target.value })}
-                    onFocus={e => e.target.select()}
-                  />
-                  <Tooltip
-                    style={{ margin: '16px 0 48px 0' }}
-                    label="Do not remember password on untrust device or browser.">
-                    <FormField input={<Checkbox
-                      checked={settings.rememberPassword}
-                      onChange={() => {
-                        const rememberPassword = !settings.rememberPassword;
-                        settings.setRememberPassword(rememberPassword);
-                        if (rememberPassword === false) {
-                          settings.setSshPassword(null);
+                  <FormField input={<Checkbox
+                    checked={usePrivateKey}
+                    onChange={() => this.setState({ usePrivateKey: !usePrivateKey })} />
+                  }>{meta.usePrivateKey}</FormField>
+                  {usePrivateKey ? <>
+                    <TextArea
+                      id='private-key'
+                      style={{ width: '100%', display: 'block', fontFamily: 'monospace' }}
+                      label={meta.privateKey}
+                      value={this.state.privateKey}
+                      onChange={(e) => this.setState({ privateKey: e.target.value })} />
+                    <input ref={this._keyFileRef}
+                      type='file'
+                      hidden
+                      onChange={async e => {
+                        const file = e.target.files?.[0];
+                        e.target.value = '';
+                        if (file === undefined) return;
+                        if (file.size > MAX_KEY_FILE_SIZE) {
+                          this.props.snackbar.showMessage({ content: `${file.name} is not a private key` });
+                          return;
                         }
+                        this.setState({ privateKey: await file.text() });
                       }} />
-                    }>{meta.rememberPassword}</FormField>
-                  </Tooltip>
+                    <Button type='button'
+                      label={meta.openKeyFile}
+                      onClick={() => this._keyFileRef.current?.click()} />
+                    <div style={{ height: '16px' }} />
+                    <TextField ref={this._passwordRef}
+                      outlined
+                      autoComplete='off'
+                      id='passphrase'
+                      type='password'
+                      name='passphrase'
+                      style={{ width: '100%', marginBottom: '48px' }}
+                      label={meta.passphrase}
+                      value={this.state.passphrase}
+                      onChange={(e) => this.setState({ passphrase: e.target.value })}
+                    />
+                  </> : <>
+                    <div style={{ height: '16px' }} />
+                    <TextField ref={this._passwordRef}
+                      outlined
+                      autoComplete='current-password'
+                      id='password'
+                      type={visibility !== null ? 'text' : 'password'}
+                      name='password'
+                      style={{ width: '100%' }}
+                      label={meta.password}
+                      value={this.state.password}
+                      trailingIcon={<IconButton
+                        type='button'
+                        style={{ alignSelf: 'center', margin: '0 4px' }}
+                        onClick={e => {
+                          e.preventDefault();
+                          if (visibility) {
+                            window.clearTimeout(visibility);
+                            this.setState({ visibility: null });
+                          } else {
+                            const visibility = window.setTimeout(() => {
+                              this.setState({ visibility: null });
+                            }, 3000);
+                            this.setState({ visibility });
+                          }
+                        }}><Icon>{visibility !== null ? "visibility" : "visibility_off"}</Icon>
+                      </IconButton>}
+                      onChange={(e) => this.setState({ password: e.target.value })}
+                      onFocus={e => e.target.select()}
+                    />
+                    <Tooltip
+                      style={{ margin: '16px 0 48px 0' }}
+                      label="Do not remember password on untrust device or browser.">
+                      <FormField input={<Checkbox
+                        checked={settings.rememberPassword}
+                        onChange={() => {
+                          const rememberPassword = !settings.rememberPassword;
+                          settings.setRememberPassword(rememberPassword);
+                          if (rememberPassword === false) {
+                            settings.setSshPassword(null);
+                          }
+                        }} />
+                      }>{meta.rememberPassword}</FormField>
+                    </Tooltip>
+                  </>}
                 </form>
               </Card>
             </LayoutBuilder>
@@ -212,10 +311,61 @@ namespace Content {
     loading: boolean,
     username: string,
     password: string,
+    usePrivateKey: boolean,
+    privateKey: string,
+    passphrase: string,
     visibility: number | null,
+    prompt?: { prompt: Server.Prompt, resolve: (answers: string[] | undefined) => void },
   }>;
 }
 
+// keyboard interactive questions, answers of the ones without echo are masked like password
+function PromptDialog({ prompt, locale: { meta }, close }: {
+  prompt?: Server.Prompt,
+  locale: LocaleContextType,
+  close: (answers: string[] | undefined) => unknown,
+}) {
+  const open = prompt !== undefined;
+  const [answers, setAnswers] = React.useState<string[]>([]);
+  const ref = useInputAutoFocusRef(open);
+  React.useEffect(() => {
+    setAnswers(prompt?.prompts.map(() => "") ?? []);
+  }, [prompt]);
+  const cancel = () => close(undefined);
+  return (
+    <Dialog
+      open={open}
+      onEscapeKey={cancel}
+      title={prompt?.name || meta.signIn}
+      actions={<>
+        <Button type='submit' label={meta.ok} form='prompt' />
+        <Button type='button' label={meta.cancel} onClick={cancel} />
+      </>}>
+      <form id='prompt'
+        onSubmit={event => {
+          event.preventDefault();
+          close(answers);
+        }}>
+        {prompt?.instructions ? <Typography.Body1>{prompt.instructions}</Typography.Body1> : undefined}
+        {prompt?.prompts.map(({ prompt, echo }, index) =>
+          <TextField
+            key={index}
+            ref={index === 0 ? ref : undefined}
+            outlined
+            autoComplete={echo ? 'off' : 'one-time-code'}
+            type={echo ? 'text' : 'password'}
+            label={prompt}
+            value={answers[index] ?? ""}
+            onChange={e => {
+              const value = e.target.value;
+              setAnswers(answers => answers.map((answer, i) => i === index ? value : answer));
+            }}
+            style={{ width: '100%', marginTop: '16px' }} />)}
+      </form>
+    </Dialog>
+  );
+}
+
 function Title() {
   return (
     <div className='full-size column flex-center' style={{ minWidth: 480 }}>
@@ -228,8 +378,9 @@ function Title() {
 }
 
 class Auth implements Server.Authentication.Type {
-  constructor(props: { server: Server.Type }) {
+  constructor(props: { server: Server.Type, resumedShells?: string[] }) {
     this._ws = props.server.ws;
+    this.resumedShells = props.resumedShells ?? [];
     this._ws.addEventListener('message', async ({ data }) => {
       const obj = await decodeMessage(data);
       if (obj === undefined) return;
@@ -242,12 +393,14 @@ class Auth implements Server.Authentication.Type {
         if ('shell' in event) this.shell.invoke(event.shell);
         else if ('watch' in event) this.watch.invoke(event.watch);
         else if ('notification' in event) this.notification.invoke(event.notification);
+        else if ('sessionExpired' in event) this.notification.invoke(`Session expired (${event.sessionExpired}), please sign in again`);
       }
     });
   }
 
   protected _ws: WebSocket;
   protected _tag = 0;
+  readonly resumedShells: string[];
   protected _callbacks = new Map<number, (response: unknown) => unknown>();
 
   readonly notification = new (class extends EventTarget {
@@ -286,8 +439,7 @@ class Auth implements Server.Authentication.Type {
     onUploadProgress?: (progress: ProgressEvent) => unknown,
     onDownloadProgress?: (progress: ProgressEvent) => unknown,
   }): Promise<Express.Multer.File> {
-    const token = await this.rest('token', []);
-    if (Rest.isError(token)) throw token.error;
+    const token = await this._token();
     return new Promise((resolve, reject) => {
       const xhr = new XMLHttpRequest();
       if (init) {
@@ -307,39 +459,59 @@ class Auth implements Server.Authentication.Type {
           reject(error);
         }
       }
-      xhr.open('POST', `https://${host}/upload?t=${token}${dest.map(value => `&u=${encodeURIComponent(value)}`).join('')}${filename === null ? '' : `&n=${encodeURIComponent(filename)}`}`, true);
+      xhr.open('POST', `https://${host}${basePath}upload?${dest.map(value => `u=${encodeURIComponent(value)}`).join('&')}${filename === null ? '' : `&n=${encodeURIComponent(filename)}`}`, true);
+      xhr.setRequestHeader('Authorization', `Bearer ${token}`);
       xhr.setRequestHeader('Content-Type', 'application/octet-stream');
       xhr.setRequestHeader('Content-Disposition', `attachment"${filename === null ? '' : `; filename=${encodeURI(filename)}`}"`);
       xhr.send(data);
     });
   }
 
-  async download(filePath: string | string[]): Promise<void> {
+  // token is only sent by header, server sets it as HttpOnly cookie that links and pages carry themselves
+  protected async _token() {
     const token = await this.rest('token', []);
     if (Rest.isError(token)) throw token.error;
+    return token;
+  }
+
+  // cookie is named by session id, links carry it so that other tabs' sessions can't be picked
+  protected async _session(): Promise<string> {
+    const token = await this._token();
+    const response = await fetch(`https://${host}${basePath}session`, { method: 'POST', headers: { Authorization: `Bearer ${token}` } });
+    if (!response.ok) throw new Error(await response.text());
+    const { session } = await response.json();
+    return encodeURIComponent(session);
+  }
+
+  async download(filePath: string | string[]): Promise<void> {
+    if (typeof filePath !== 'string' && filePath.length === 0) return;
+    const session = await this._session();
     const element = document.createElement('a');
     if (typeof filePath === 'string') {
-      element.setAttribute('href', `https://${host}/download?t=${token}&p=${encodeURIComponent(filePath)}`);
+      element.setAttribute('href', `https://${host}${basePath}download?s=${session}&p=${encodeURIComponent(filePath)}`);
       element.setAttribute('download', "");
     } else {
-      if (filePath.length === 0) return;
-      element.setAttribute('href', `https://${host}/download?t=${token}${filePath.map(value => `&p=${encodeURIComponent(value)}`).join('')}`);
+      element.setAttribute('href', `https://${host}${basePath}download?s=${session}&${filePath.map(value => `p=${encodeURIComponent(value)}`).join('&')}`);
       element.setAttribute('download', 'bundle.zip');
     }
     element.click();
   }
 
   async previewUrl(path: string) {
-    const token = await this.rest('token', []);
-    if (Rest.isError(token)) throw token.error;
-    return new URL(`https://${host}/preview?t=${token}&v=${encodeURIComponent(path)}`);
+    const session = await this._session();
+    return new URL(`https://${host}${basePath}preview?s=${session}&v=${encodeURIComponent(path)}`);
   }
 
   async preview(path: string) {
     window.open(await this.previewUrl(path));
   }
 
-  signOut() { wsSafeClose(this._ws) }
+  signOut() {
+    sessionStorage.removeItem(SESSION_KEY);
+    // normal closure tells server not to keep the session for resuming
+    if (this._ws.readyState === WebSocket.OPEN) this._ws.close(1000);
+    else wsSafeClose(this._ws);
+  }
 
 }
 
@@ -14,7 +14,9 @@ import HomePage from "../HomePage";
 class MultiTerminalView extends React.Component<MultiTerminalView.Props, MultiTerminalView.State> {
   constructor(props: MultiTerminalView.Props) {
     super(props);
-    this._controllers = [new MultiTerminalView.Controller({ auth: props.auth, id: makeId(4), textDecoder: this._textDecoder })];
+    const { resumedShells } = props.auth;
+    const ids = resumedShells.length === 0 ? [makeId(4)] : resumedShells;
+    this._controllers = ids.map(id => new MultiTerminalView.Controller({ auth: props.auth, id, textDecoder: this._textDecoder }));
     this.state = {
       controller: this._controllers[0],
       controllers: this._controllers
@@ -39,6 +39,8 @@ export default defineConfig(({ command }): UserConfig => {
             legalComments: "none",
             drop: ["console", "debugger"],
         },
+        // relative asset urls, so the app works under any '--base-path'
+        base: './',
         build: {
             outDir: "build",
         },